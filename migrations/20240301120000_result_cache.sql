@@ -0,0 +1,10 @@
+CREATE TABLE result_cache (
+    -- Kind of request, followed by the JSON-serialized request
+    key TEXT NOT NULL PRIMARY KEY,
+    -- JSON-serialized result of the request
+    value TEXT NOT NULL,
+    -- Unix timestamp in seconds of when the result was stored
+    created_at INTEGER NOT NULL
+);
+
+CREATE INDEX result_cache_created_at_idx ON result_cache(created_at);
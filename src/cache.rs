@@ -0,0 +1,176 @@
+//! Cache for the results of requests to the playground and godbolt, keyed by the serialized
+//! request. Identical requests are common: every edit-tracked rerun, retry and copied snippet
+//! would otherwise be sent upstream again
+
+use crate::Error;
+
+use std::collections::HashMap;
+
+struct CacheEntry {
+    /// JSON-serialized result
+    value: String,
+    /// Unix timestamp in seconds
+    created_at: i64,
+}
+
+pub struct ResultCache {
+    entries: std::sync::Mutex<HashMap<String, CacheEntry>>,
+    /// Entries older than this are discarded
+    ttl: std::time::Duration,
+    /// When there are more entries than this, the oldest ones are discarded
+    max_entries: usize,
+    /// If set, entries are also stored in this database, so that they survive restarts
+    database: Option<sqlx::SqlitePool>,
+}
+
+fn unix_time() -> i64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map_or(0, |time| time.as_secs() as i64)
+}
+
+impl ResultCache {
+    pub fn new(
+        ttl: std::time::Duration,
+        max_entries: usize,
+        database: Option<sqlx::SqlitePool>,
+    ) -> Self {
+        Self {
+            entries: std::sync::Mutex::new(HashMap::new()),
+            ttl,
+            max_entries,
+            database,
+        }
+    }
+
+    /// `kind` distinguishes requests which serialize the same but go to different endpoints
+    fn key(kind: &str, request: &impl serde::Serialize) -> Result<String, Error> {
+        Ok(format!("{}:{}", kind, serde_json::to_string(request)?))
+    }
+
+    fn oldest_allowed_time(&self) -> i64 {
+        unix_time() - self.ttl.as_secs() as i64
+    }
+
+    /// Returns the cached result of an identical earlier request, if there is one
+    pub async fn get<T: serde::de::DeserializeOwned>(
+        &self,
+        kind: &str,
+        request: &impl serde::Serialize,
+    ) -> Option<T> {
+        let key = Self::key(kind, request).ok()?;
+        let oldest_allowed_time = self.oldest_allowed_time();
+
+        let cached_value = self
+            .entries
+            .lock()
+            .unwrap()
+            .get(&key)
+            .filter(|entry| entry.created_at >= oldest_allowed_time)
+            .map(|entry| entry.value.clone());
+        let value = match (cached_value, &self.database) {
+            (Some(value), _) => value,
+            (None, Some(database)) => {
+                let row = sqlx::query!(
+                    "SELECT value FROM result_cache WHERE key = ? AND created_at >= ?",
+                    key,
+                    oldest_allowed_time,
+                )
+                .fetch_optional(database)
+                .await;
+                match row {
+                    Ok(row) => row?.value,
+                    Err(e) => {
+                        log::warn!("couldn't read result cache from database: {}", e);
+                        return None;
+                    }
+                }
+            }
+            (None, None) => return None,
+        };
+
+        log::info!("result cache hit for {} request", kind);
+        serde_json::from_str(&value).ok()
+    }
+
+    /// Stores the result of a request, evicting expired entries and, if there are too many, the
+    /// oldest ones
+    pub async fn insert(
+        &self,
+        kind: &str,
+        request: &impl serde::Serialize,
+        result: &impl serde::Serialize,
+    ) {
+        let (key, value) = match (Self::key(kind, request), serde_json::to_string(result)) {
+            (Ok(key), Ok(value)) => (key, value),
+            _ => return,
+        };
+        let created_at = unix_time();
+        let oldest_allowed_time = self.oldest_allowed_time();
+
+        {
+            let mut entries = self.entries.lock().unwrap();
+            entries.retain(|_, entry| entry.created_at >= oldest_allowed_time);
+            while entries.len() >= self.max_entries {
+                let oldest_key = match entries.iter().min_by_key(|(_, entry)| entry.created_at) {
+                    Some((key, _)) => key.clone(),
+                    None => break,
+                };
+                entries.remove(&oldest_key);
+            }
+            entries.insert(
+                key.clone(),
+                CacheEntry {
+                    value: value.clone(),
+                    created_at,
+                },
+            );
+        }
+
+        if let Some(database) = &self.database {
+            let max_entries = self.max_entries as i64;
+            let result = async {
+                sqlx::query!(
+                    "INSERT OR REPLACE INTO result_cache (key, value, created_at) VALUES (?, ?, ?)",
+                    key,
+                    value,
+                    created_at,
+                )
+                .execute(database)
+                .await?;
+                sqlx::query!(
+                    "DELETE FROM result_cache WHERE created_at < ? OR key NOT IN \
+                    (SELECT key FROM result_cache ORDER BY created_at DESC LIMIT ?)",
+                    oldest_allowed_time,
+                    max_entries,
+                )
+                .execute(database)
+                .await?;
+                Ok::<_, sqlx::Error>(())
+            };
+            if let Err(e) = result.await {
+                log::warn!("couldn't write result cache to database: {}", e);
+            }
+        }
+    }
+
+    /// Returns the cached result of an identical earlier request, or runs `compute` and caches
+    /// its result if successful
+    pub async fn get_or_insert_with<T, F>(
+        &self,
+        kind: &str,
+        request: &impl serde::Serialize,
+        compute: F,
+    ) -> Result<T, Error>
+    where
+        T: serde::Serialize + serde::de::DeserializeOwned,
+        F: std::future::Future<Output = Result<T, Error>>,
+    {
+        if let Some(result) = self.get(kind, request).await {
+            return Ok(result);
+        }
+        let result = compute.await?;
+        self.insert(kind, request, &result).await;
+        Ok(result)
+    }
+}
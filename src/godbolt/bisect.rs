@@ -0,0 +1,381 @@
+use super::{compile_rust_source_cached, rustc_releases, GodboltRequest, RustcRelease};
+use crate::{Context, Error};
+
+/// Program output longer than this is cut off in the bisection summary
+const MAX_DISPLAYED_OUTPUT_LENGTH: usize = 400;
+
+/// What decides whether two releases behave the same
+#[derive(Clone, Copy, PartialEq)]
+enum Check {
+    /// Whether the code compiles
+    Compile,
+    /// What the compiled program prints
+    Output,
+}
+
+impl std::str::FromStr for Check {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Error> {
+        match s {
+            "compile" => Ok(Self::Compile),
+            "output" => Ok(Self::Output),
+            _ => Err(format!("invalid `check` value `{}`", s).into()),
+        }
+    }
+}
+
+/// How the code behaved with a single release
+#[derive(PartialEq)]
+enum Outcome {
+    CompileError,
+    /// The code compiled and wasn't run
+    Compiled,
+    /// The code compiled and ran, printing the given output
+    Ran(String),
+}
+
+struct Evaluation {
+    outcome: Outcome,
+    /// First line of the compiler error, if the code didn't compile
+    error: Option<String>,
+}
+
+impl Evaluation {
+    fn describe(&self) -> String {
+        match &self.outcome {
+            Outcome::CompileError => match &self.error {
+                Some(error) => format!("doesn't compile: `{}`", error),
+                None => "doesn't compile".to_owned(),
+            },
+            Outcome::Compiled => "compiles".to_owned(),
+            Outcome::Ran(output) => {
+                let mut end = output.len().min(MAX_DISPLAYED_OUTPUT_LENGTH);
+                while !output.is_char_boundary(end) {
+                    end -= 1;
+                }
+                let ellipsis = if end < output.len() { "…" } else { "" };
+                format!("prints\n```\n{}{}\n```", output[..end].trim_end(), ellipsis)
+            }
+        }
+    }
+}
+
+struct BisectOptions<'a> {
+    check: Check,
+    edition: &'a str,
+    /// Additional flags to pass to rustc
+    flags: &'a str,
+}
+
+impl<'a> BisectOptions<'a> {
+    fn from_key_value_args(params: &'a poise::KeyValueArgs) -> Result<Self, Error> {
+        let edition = params.get("edition").unwrap_or("2021");
+        if edition_first_release(edition).is_none() {
+            return Err(format!("invalid `edition` value `{}`", edition).into());
+        }
+
+        Ok(Self {
+            check: params.get("check").map_or(Ok(Check::Compile), str::parse)?,
+            edition,
+            flags: params.get("flags").unwrap_or(""),
+        })
+    }
+}
+
+/// First release that supports the given edition, or None if the edition doesn't exist
+fn edition_first_release(edition: &str) -> Option<(u16, u16, u16)> {
+    match edition {
+        "2015" => Some((1, 0, 0)),
+        "2018" => Some((1, 31, 0)),
+        "2021" => Some((1, 56, 0)),
+        "2024" => Some((1, 85, 0)),
+        _ => None,
+    }
+}
+
+/// Parses versions like `1.45.2` or `1.45`
+fn parse_version(version: &str) -> Option<(u16, u16, u16)> {
+    let mut parts = version.trim().splitn(3, '.');
+    let major = parts.next()?.parse().ok()?;
+    let minor = parts.next()?.parse().ok()?;
+    let patch = match parts.next() {
+        Some(patch) => patch.parse().ok()?,
+        None => 0,
+    };
+    Some((major, minor, patch))
+}
+
+fn first_error_line(stderr: &str) -> Option<String> {
+    stderr
+        .lines()
+        .find(|line| line.starts_with("error"))
+        .map(str::to_owned)
+}
+
+async fn evaluate(
+    ctx: Context<'_>,
+    code: &str,
+    release: &RustcRelease,
+    options: &BisectOptions<'_>,
+) -> Result<Evaluation, Error> {
+    // Not worth a request, and the compiler error would be confusing
+    if edition_first_release(options.edition).is_some_and(|first| release.version < first) {
+        return Ok(Evaluation {
+            outcome: Outcome::CompileError,
+            error: Some(format!("edition {} isn't supported yet", options.edition)),
+        });
+    }
+
+    // Releases from before editions existed reject the flag, even for 2015
+    let mut flags = match options.edition {
+        "2015" => String::new(),
+        edition => format!("--edition={}", edition),
+    };
+    flags += " ";
+    flags += options.flags;
+
+    let godbolt_request = GodboltRequest {
+        source_code: code,
+        rustc: &release.id,
+        flags: &flags,
+        run_llvm_mca: false,
+        execute: options.check == Check::Output,
+    };
+    let compilation = compile_rust_source_cached(ctx.data(), &godbolt_request).await?;
+    Ok(match (compilation.success, options.check) {
+        (false, _) => Evaluation {
+            outcome: Outcome::CompileError,
+            error: first_error_line(&compilation.stderr),
+        },
+        (true, Check::Compile) => Evaluation {
+            outcome: Outcome::Compiled,
+            error: None,
+        },
+        (true, Check::Output) => Evaluation {
+            outcome: Outcome::Ran(compilation.output),
+            error: None,
+        },
+    })
+}
+
+struct Bisection {
+    /// Every tested release (as index into the release list) and its evaluation, in testing order
+    evaluations: Vec<(usize, Evaluation)>,
+    /// Last release which behaves like the oldest one and first release which doesn't, if the
+    /// newest release behaves differently than the oldest one
+    change: Option<(usize, usize)>,
+}
+
+impl Bisection {
+    fn evaluation(&self, release_index: usize) -> &Evaluation {
+        self.evaluations
+            .iter()
+            .find(|(index, _)| *index == release_index)
+            .map(|(_, evaluation)| evaluation)
+            .expect("release wasn't evaluated")
+    }
+}
+
+/// Binary-searches the releases for the first one that behaves differently than the oldest one.
+/// Assumes that the behavior changes at most once. `releases` must contain at least one release
+async fn run_bisection(
+    ctx: Context<'_>,
+    code: &str,
+    releases: &[RustcRelease],
+    options: &BisectOptions<'_>,
+) -> Result<Bisection, Error> {
+    let (mut old, mut new) = (0, releases.len() - 1);
+    let mut evaluations = vec![
+        (old, evaluate(ctx, code, &releases[old], options).await?),
+        (new, evaluate(ctx, code, &releases[new], options).await?),
+    ];
+    if evaluations[0].1.outcome == evaluations[1].1.outcome {
+        return Ok(Bisection {
+            evaluations,
+            change: None,
+        });
+    }
+
+    // Invariant: `old` behaves like the oldest release, `new` doesn't
+    while new - old > 1 {
+        let middle = (old + new) / 2;
+        let evaluation = evaluate(ctx, code, &releases[middle], options).await?;
+        if evaluation.outcome == evaluations[0].1.outcome {
+            old = middle;
+        } else {
+            new = middle;
+        }
+        evaluations.push((middle, evaluation));
+    }
+
+    Ok(Bisection {
+        evaluations,
+        change: Some((old, new)),
+    })
+}
+
+/// Returns the releases within the `from` and `to` parameters
+fn select_releases(
+    releases: Vec<RustcRelease>,
+    params: &poise::KeyValueArgs,
+) -> Result<Vec<RustcRelease>, Error> {
+    let parse_param = |name: &str| {
+        params
+            .get(name)
+            .map(|version| {
+                parse_version(version)
+                    .ok_or_else(|| format!("invalid `{}` version `{}`", name, version))
+            })
+            .transpose()
+    };
+    let from = parse_param("from")?.unwrap_or((0, 0, 0));
+    let to = parse_param("to")?.unwrap_or((u16::MAX, u16::MAX, u16::MAX));
+
+    let releases = releases
+        .into_iter()
+        .filter(|release| (from..=to).contains(&release.version))
+        .collect::<Vec<_>>();
+    if releases.len() < 2 {
+        return Err(
+            "Need at least two rustc releases to bisect. Run ?targets for a full list".into(),
+        );
+    }
+    Ok(releases)
+}
+
+/// Find the rustc release in which code started or stopped compiling
+///
+/// Binary-searches the stable rustc releases on <https://rust.godbolt.org> for the first one \
+/// where the code starts or stops compiling, or where the output of the program changes. \
+/// Assumes that this happened only once in the searched range.
+/// ```
+/// ?bisect from={} to={} edition={} check={} flags={} ``​`
+/// fn main() {
+///     // Code
+/// }
+/// ``​`
+/// ```
+/// Optional arguments:
+/// - `from`: oldest release to test, like `1.40.0`. Defaults to the oldest release on Godbolt
+/// - `to`: newest release to test. Defaults to the latest release
+/// - `edition`: `2015`, `2018`, `2021` or `2024`. Defaults to `2021`. Releases that don't support the edition count as not compiling
+/// - `check`: `compile` to look for changes in whether the code compiles, `output` to look for changes in what the program prints. Defaults to `compile`
+/// - `flags`: additional flags to pass to rustc
+#[poise::command(prefix_command, broadcast_typing, track_edits, category = "Godbolt")]
+pub async fn bisect(
+    ctx: Context<'_>,
+    params: poise::KeyValueArgs,
+    code: poise::CodeBlock,
+) -> Result<(), Error> {
+    let options = BisectOptions::from_key_value_args(&params)?;
+    let releases = select_releases(rustc_releases(ctx.data()).await, &params)?;
+    let bisection = run_bisection(ctx, &code.code, &releases, &options).await?;
+
+    let mut response = format!(
+        "Bisected {} releases from {} to {}\n",
+        releases.len(),
+        releases[0].semver,
+        releases[releases.len() - 1].semver,
+    );
+    match bisection.change {
+        None => {
+            response += &format!(
+                "No change, the code {} on both\n",
+                bisection.evaluation(0).describe()
+            );
+        }
+        Some((old, new)) => {
+            let old_evaluation = bisection.evaluation(old);
+            let new_evaluation = bisection.evaluation(new);
+            let summary = match (&old_evaluation.outcome, &new_evaluation.outcome) {
+                (Outcome::CompileError, _) => "Compiles since",
+                (_, Outcome::CompileError) => "Stops compiling in",
+                _ => "Output changed in",
+            };
+            response += &format!(
+                "{} **{}**\n- {} {}\n- {} {}\n",
+                summary,
+                releases[new].semver,
+                releases[old].semver,
+                old_evaluation.describe(),
+                releases[new].semver,
+                new_evaluation.describe(),
+            );
+        }
+    }
+
+    response += "Tested: ";
+    response += &bisection
+        .evaluations
+        .iter()
+        .map(|(index, evaluation)| {
+            let symbol = match &evaluation.outcome {
+                Outcome::CompileError => "❌",
+                Outcome::Compiled => "✅",
+                Outcome::Ran(_) if evaluation.outcome == bisection.evaluation(0).outcome => "✅",
+                // Runs, but the output differs from the oldest release
+                Outcome::Ran(_) => "🔀",
+            };
+            format!("{} {}", releases[*index].semver, symbol)
+        })
+        .collect::<Vec<_>>()
+        .join(", ");
+
+    ctx.say(response).await?;
+    Ok(())
+}
+
+/// Find the minimum supported Rust version of some code
+///
+/// Binary-searches the stable rustc releases on <https://rust.godbolt.org> for the oldest one \
+/// that compiles the code, assuming that all newer releases compile it as well.
+/// ```
+/// ?msrv edition={} flags={} ``​`
+/// pub fn your_function() {
+///     // Code
+/// }
+/// ``​`
+/// ```
+/// Optional arguments:
+/// - `edition`: `2015`, `2018`, `2021` or `2024`. Defaults to `2021`
+/// - `flags`: additional flags to pass to rustc
+#[poise::command(prefix_command, broadcast_typing, track_edits, category = "Godbolt")]
+pub async fn msrv(
+    ctx: Context<'_>,
+    params: poise::KeyValueArgs,
+    code: poise::CodeBlock,
+) -> Result<(), Error> {
+    let options = BisectOptions {
+        check: Check::Compile,
+        ..BisectOptions::from_key_value_args(&params)?
+    };
+    let releases = rustc_releases(ctx.data()).await;
+    if releases.len() < 2 {
+        return Err("Couldn't retrieve the list of rustc releases from Godbolt".into());
+    }
+    let bisection = run_bisection(ctx, &code.code, &releases, &options).await?;
+
+    let newest = releases.len() - 1;
+    let response = match bisection.change {
+        _ if bisection.evaluation(newest).outcome == Outcome::CompileError => format!(
+            "The code doesn't compile on the latest release {} either, so it has no MSRV\n- {} {}",
+            releases[newest].semver,
+            releases[newest].semver,
+            bisection.evaluation(newest).describe(),
+        ),
+        Some((old, new)) => format!(
+            "MSRV: **{}**\n- {} {}",
+            releases[new].semver,
+            releases[old].semver,
+            bisection.evaluation(old).describe(),
+        ),
+        None => format!(
+            "The code compiles on every release since {}, the oldest one on Godbolt",
+            releases[0].semver
+        ),
+    };
+
+    ctx.say(response).await?;
+    Ok(())
+}
@@ -1,10 +1,14 @@
+mod bisect;
 mod targets;
+pub use bisect::*;
 pub use targets::*;
 
-use crate::{Context, Error};
+use crate::{Context, Data, Error};
+use poise::serenity_prelude as serenity;
 
 const LLVM_MCA_TOOL_ID: &str = "llvm-mcatrunk";
 
+#[derive(serde::Serialize, serde::Deserialize)]
 struct Compilation {
     output: String,
     stderr: String,
@@ -39,6 +43,21 @@ struct GodboltResponse {
     tools: Vec<GodboltTool>,
 }
 
+/// Response to a request with `execute` set
+#[derive(Debug, serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct GodboltExecution {
+    code: i32,
+    did_execute: bool,
+    stdout: GodboltOutput,
+    build_result: GodboltBuildResult,
+}
+
+#[derive(Debug, serde::Deserialize)]
+struct GodboltBuildResult {
+    stderr: GodboltOutput,
+}
+
 #[derive(Debug, serde::Deserialize)]
 struct GodboltTool {
     id: String,
@@ -47,16 +66,21 @@ struct GodboltTool {
     // stderr: GodboltOutput,
 }
 
+#[derive(serde::Serialize)]
 struct GodboltRequest<'a> {
     source_code: &'a str,
     rustc: &'a str,
     flags: &'a str,
     run_llvm_mca: bool,
+    /// Whether to compile the code as a binary and run it. The output is the program's stdout
+    /// then, and the compilation only counts as successful if the program could be run
+    execute: bool,
 }
 
 /// Compile a given Rust source code file on Godbolt using the latest nightly compiler with
 /// full optimizations (-O3)
-/// Returns a multiline string with the pretty printed assembly
+/// Returns a multiline string with the pretty printed assembly, or with the program output if the
+/// request asks to execute the code
 async fn compile_rust_source(
     http: &reqwest::Client,
     request: &GodboltRequest<'_>,
@@ -71,6 +95,18 @@ async fn compile_rust_source(
         }
     };
 
+    let mut options = serde_json::json! { {
+        "userArguments": format!("{} --color=never", request.flags),
+        "tools": tools,
+        // "libraries": [{"id": "itoa", "version": "102"}],
+    } };
+    if request.execute {
+        // Only set when executing, because a partial filter object would replace the default
+        // filters of the assembly output
+        options["compilerOptions"] = serde_json::json!({ "executorRequest": true });
+        options["filters"] = serde_json::json!({ "execute": true });
+    }
+
     let http_request = http
         .post(&format!(
             "https://godbolt.org/api/compiler/{}/compile",
@@ -79,17 +115,27 @@ async fn compile_rust_source(
         .header(reqwest::header::ACCEPT, "application/json") // to make godbolt respond in JSON
         .json(&serde_json::json! { {
             "source": request.source_code,
-            "options": {
-                "userArguments": format!("{} --color=never", request.flags),
-                "tools": tools,
-                // "libraries": [{"id": "itoa", "version": "102"}],
-            },
+            "options": options,
         } })
         .build()?;
+    let http_response = http.execute(http_request).await?;
+
+    if request.execute {
+        let execution: GodboltExecution = http_response.json().await?;
+        let mut output = execution.stdout.concatenate();
+        if execution.did_execute && execution.code != 0 {
+            output += &format!("(exit code {})\n", execution.code);
+        }
+        return Ok(Compilation {
+            output,
+            stderr: execution.build_result.stderr.concatenate(),
+            success: execution.did_execute,
+        });
+    }
 
-    let response: GodboltResponse = http.execute(http_request).await?.json().await?;
+    let response: GodboltResponse = http_response.json().await?;
 
-    // TODO: use the extract_relevant_lines utility to strip stderr nicely
+    // TODO: use the playground diagnostics parser to strip stderr nicely
     Ok(Compilation {
         output: if request.run_llvm_mca {
             let text = response
@@ -110,6 +156,20 @@ async fn compile_rust_source(
     })
 }
 
+/// Like [`compile_rust_source`], but waits for a free slot in the godbolt queue first, and reuses
+/// the result of an identical earlier request if possible
+async fn compile_rust_source_cached(
+    data: &Data,
+    request: &GodboltRequest<'_>,
+) -> Result<Compilation, Error> {
+    data.result_cache
+        .get_or_insert_with("godbolt", request, async {
+            let _permit = data.godbolt_queue.acquire().await;
+            compile_rust_source(&data.http, request).await
+        })
+        .await
+}
+
 async fn save_to_shortlink(http: &reqwest::Client, req: &GodboltRequest<'_>) -> String {
     #[derive(serde::Deserialize)]
     struct GodboltShortenerResponse {
@@ -157,6 +217,34 @@ async fn save_to_shortlink(http: &reqwest::Client, req: &GodboltRequest<'_>) ->
     })
 }
 
+/// Optional arguments shared by the Godbolt commands. Prefix commands receive them as `key=value`
+/// arguments, slash commands as typed options
+struct GodboltParams<'a> {
+    /// Compiler version, like `nightly`, `beta` or `1.45.2`
+    rustc: Option<&'a str>,
+    /// Flags to pass to rustc
+    flags: Option<&'a str>,
+    /// How to deliver output that doesn't fit into a single message
+    overflow: Option<crate::OverflowStrategy>,
+}
+
+impl<'a> GodboltParams<'a> {
+    fn from_key_value_args(params: &'a poise::KeyValueArgs) -> Result<Self, Error> {
+        Ok(Self {
+            rustc: params.get("rustc"),
+            flags: params.get("flags"),
+            overflow: params
+                .get("overflow")
+                .map(|overflow| {
+                    overflow
+                        .parse()
+                        .map_err(|_| format!("invalid `overflow` value `{}`", overflow))
+                })
+                .transpose()?,
+        })
+    }
+}
+
 #[derive(PartialEq, Clone, Copy)]
 enum GodboltMode {
     Asm,
@@ -170,21 +258,42 @@ async fn respond_codeblock(
     text: &str,
     note: &str,
     godbolt_request: &GodboltRequest<'_>,
+    overflow: crate::OverflowStrategy,
 ) -> Result<(), Error> {
-    ctx.say(
-        crate::trim_text(
-            &format!("```{}\n{}", codeblock_lang, text),
-            &format!("\n```{}", note),
-            async {
-                format!(
-                    "Output too large. Godbolt link: <{}>",
-                    save_to_shortlink(&ctx.data().http, &godbolt_request).await,
-                )
-            },
-        )
-        .await,
-    )
-    .await?;
+    let text_start = format!("```{}\n", codeblock_lang);
+    let text_end = format!("\n```{}", note);
+
+    if overflow == crate::OverflowStrategy::Paginate {
+        return crate::paginate::paginate_text(ctx, &text_start, text, &text_end).await;
+    }
+
+    let mut attach_output = false;
+    let content = crate::trim_text(&format!("{}{}", text_start, text), &text_end, async {
+        if overflow == crate::OverflowStrategy::Link {
+            format!(
+                "Output too large. Godbolt link: <{}>",
+                save_to_shortlink(&ctx.data().http, &godbolt_request).await,
+            )
+        } else {
+            attach_output = true;
+            "Output too large, see the attached file".to_owned()
+        }
+    })
+    .await;
+
+    let mut reply = poise::CreateReply::new().content(content);
+    if attach_output {
+        let extension = match codeblock_lang {
+            "x86asm" => "asm",
+            "llvm" => "ll",
+            _ => "txt",
+        };
+        reply = reply.attachment(serenity::CreateAttachment::bytes(
+            text.as_bytes().to_vec(),
+            format!("output.{}", extension),
+        ));
+    }
+    ctx.send(reply).await?;
     Ok(())
 }
 
@@ -193,7 +302,7 @@ async fn respond_codeblock(
 /// Compile Rust code using <https://rust.godbolt.org>. Full optimizations are applied unless \
 /// overriden.
 /// ```
-/// ?godbolt flags={} rustc={} ``​`
+/// ?godbolt flags={} rustc={} overflow={} ``​`
 /// pub fn your_function() {
 ///     // Code
 /// }
@@ -202,23 +311,54 @@ async fn respond_codeblock(
 /// Optional arguments:
 /// - `flags`: flags to pass to rustc invocation. Defaults to `"-Copt-level=3 --edition=2021"`
 /// - `rustc`: compiler version to invoke. Defaults to `nightly`. Possible values: `nightly`, `beta` or full version like `1.45.2`
+/// - `overflow`: what to do with long output. Defaults to `attachment`. Possible values: `link`, `attachment` or `paginate`
 #[poise::command(prefix_command, broadcast_typing, track_edits, category = "Godbolt")]
 pub async fn godbolt(
     ctx: Context<'_>,
     params: poise::KeyValueArgs,
     code: poise::CodeBlock,
+) -> Result<(), Error> {
+    let params = GodboltParams::from_key_value_args(&params)?;
+    godbolt_inner(ctx, &code.code, params).await
+}
+
+/// View assembly using Godbolt
+#[poise::command(slash_command)]
+pub async fn application_godbolt(
+    ctx: Context<'_>,
+    #[description = "Compiler version, like nightly, beta or 1.45.2"]
+    #[autocomplete = "autocomplete_rustc"]
+    rustc: Option<String>,
+    #[description = "Flags to pass to rustc. Defaults to -Copt-level=3 --edition=2021"]
+    flags: Option<String>,
+    #[description = "How to send long output"] overflow: Option<crate::OverflowStrategy>,
+) -> Result<(), Error> {
+    let code = crate::code_from_modal(ctx).await?;
+    let params = GodboltParams {
+        rustc: rustc.as_deref(),
+        flags: flags.as_deref(),
+        overflow,
+    };
+    godbolt_inner(ctx, &code, params).await
+}
+
+async fn godbolt_inner(
+    ctx: Context<'_>,
+    code: &str,
+    params: GodboltParams<'_>,
 ) -> Result<(), Error> {
     let (rustc, flags) = rustc_id_and_flags(ctx.data(), &params).await?;
     let godbolt_request = GodboltRequest {
-        source_code: &code.code,
+        source_code: code,
         rustc: &rustc,
         flags: &flags,
         run_llvm_mca: false,
+        execute: false,
     };
-    let godbolt_result = compile_rust_source(&ctx.data().http, &godbolt_request).await?;
+    let godbolt_result = compile_rust_source_cached(ctx.data(), &godbolt_request).await?;
 
     let text = crate::merge_output_and_errors(&godbolt_result.output, &godbolt_result.stderr);
-    let note = if code.code.contains("pub fn") {
+    let note = if code.contains("pub fn") {
         "Note: only public functions (`pub fn`) are shown"
     } else {
         ""
@@ -228,7 +368,17 @@ pub async fn godbolt(
     } else {
         "rust"
     };
-    respond_codeblock(ctx, codeblock_lang, &text, note, &godbolt_request).await?;
+    respond_codeblock(
+        ctx,
+        codeblock_lang,
+        &text,
+        note,
+        &godbolt_request,
+        params
+            .overflow
+            .unwrap_or(crate::OverflowStrategy::Attachment),
+    )
+    .await?;
 
     Ok(())
 }
@@ -238,7 +388,7 @@ pub async fn godbolt(
 /// Run the performance analysis tool llvm-mca using <https://rust.godbolt.org>. Full optimizations \
 /// are applied unless overriden.
 /// ```
-/// ?mca flags={} rustc={} ``​`
+/// ?mca flags={} rustc={} overflow={} ``​`
 /// pub fn your_function() {
 ///     // Code
 /// }
@@ -247,29 +397,64 @@ pub async fn godbolt(
 /// Optional arguments:
 /// - `flags`: flags to pass to rustc invocation. Defaults to `"-Copt-level=3 --edition=2021"`
 /// - `rustc`: compiler version to invoke. Defaults to `nightly`. Possible values: `nightly`, `beta` or full version like `1.45.2`
+/// - `overflow`: what to do with long output. Defaults to `paginate`. Possible values: `link`, `attachment` or `paginate`
 #[poise::command(prefix_command, broadcast_typing, track_edits, category = "Godbolt")]
 pub async fn mca(
     ctx: Context<'_>,
     params: poise::KeyValueArgs,
     code: poise::CodeBlock,
 ) -> Result<(), Error> {
+    let params = GodboltParams::from_key_value_args(&params)?;
+    mca_inner(ctx, &code.code, params).await
+}
+
+/// Run performance analysis using llvm-mca
+#[poise::command(slash_command)]
+pub async fn application_mca(
+    ctx: Context<'_>,
+    #[description = "Compiler version, like nightly, beta or 1.45.2"]
+    #[autocomplete = "autocomplete_rustc"]
+    rustc: Option<String>,
+    #[description = "Flags to pass to rustc. Defaults to -Copt-level=3 --edition=2021"]
+    flags: Option<String>,
+    #[description = "How to send long output"] overflow: Option<crate::OverflowStrategy>,
+) -> Result<(), Error> {
+    let code = crate::code_from_modal(ctx).await?;
+    let params = GodboltParams {
+        rustc: rustc.as_deref(),
+        flags: flags.as_deref(),
+        overflow,
+    };
+    mca_inner(ctx, &code, params).await
+}
+
+async fn mca_inner(ctx: Context<'_>, code: &str, params: GodboltParams<'_>) -> Result<(), Error> {
     let (rustc, flags) = rustc_id_and_flags(ctx.data(), &params).await?;
     let godbolt_request = GodboltRequest {
-        source_code: &code.code,
+        source_code: code,
         rustc: &rustc,
         flags: &flags,
         run_llvm_mca: true,
+        execute: false,
     };
 
-    let godbolt_result = compile_rust_source(&ctx.data().http, &godbolt_request).await?;
+    let godbolt_result = compile_rust_source_cached(ctx.data(), &godbolt_request).await?;
 
     let text = crate::merge_output_and_errors(&godbolt_result.output, &godbolt_result.stderr);
-    let note = if code.code.contains("pub fn") {
+    let note = if code.contains("pub fn") {
         ""
     } else {
         "Note: only public functions (`pub fn`) are shown"
     };
-    respond_codeblock(ctx, "rust", &text, note, &godbolt_request).await?;
+    respond_codeblock(
+        ctx,
+        "rust",
+        &text,
+        note,
+        &godbolt_request,
+        params.overflow.unwrap_or(crate::OverflowStrategy::Paginate),
+    )
+    .await?;
 
     Ok(())
 }
@@ -281,7 +466,7 @@ pub async fn mca(
 ///
 /// Equivalent to ?godbolt but with extra flags `--emit=llvm-ir -Cdebuginfo=0`.
 /// ```
-/// ?llvmir flags={} rustc={} ``​`
+/// ?llvmir flags={} rustc={} overflow={} ``​`
 /// pub fn your_function() {
 ///     // Code
 /// }
@@ -290,20 +475,51 @@ pub async fn mca(
 /// Optional arguments:
 /// - `flags`: flags to pass to rustc invocation. Defaults to `"-Copt-level=3 --edition=2021"`
 /// - `rustc`: compiler version to invoke. Defaults to `nightly`. Possible values: `nightly`, `beta` or full version like `1.45.2`
+/// - `overflow`: what to do with long output. Defaults to `attachment`. Possible values: `link`, `attachment` or `paginate`
 #[poise::command(prefix_command, broadcast_typing, track_edits, category = "Godbolt")]
 pub async fn llvmir(
     ctx: Context<'_>,
     params: poise::KeyValueArgs,
     code: poise::CodeBlock,
+) -> Result<(), Error> {
+    let params = GodboltParams::from_key_value_args(&params)?;
+    llvmir_inner(ctx, &code.code, params).await
+}
+
+/// View LLVM IR using Godbolt
+#[poise::command(slash_command)]
+pub async fn application_llvmir(
+    ctx: Context<'_>,
+    #[description = "Compiler version, like nightly, beta or 1.45.2"]
+    #[autocomplete = "autocomplete_rustc"]
+    rustc: Option<String>,
+    #[description = "Flags to pass to rustc. Defaults to -Copt-level=3 --edition=2021"]
+    flags: Option<String>,
+    #[description = "How to send long output"] overflow: Option<crate::OverflowStrategy>,
+) -> Result<(), Error> {
+    let code = crate::code_from_modal(ctx).await?;
+    let params = GodboltParams {
+        rustc: rustc.as_deref(),
+        flags: flags.as_deref(),
+        overflow,
+    };
+    llvmir_inner(ctx, &code, params).await
+}
+
+async fn llvmir_inner(
+    ctx: Context<'_>,
+    code: &str,
+    params: GodboltParams<'_>,
 ) -> Result<(), Error> {
     let (rustc, flags) = rustc_id_and_flags(ctx.data(), &params).await?;
     let godbolt_request = GodboltRequest {
-        source_code: &code.code,
+        source_code: code,
         rustc: &rustc,
         flags: &(flags + " --emit=llvm-ir -Cdebuginfo=0"),
         run_llvm_mca: false,
+        execute: false,
     };
-    let godbolt_result = compile_rust_source(&ctx.data().http, &godbolt_request).await?;
+    let godbolt_result = compile_rust_source_cached(ctx.data(), &godbolt_request).await?;
 
     let text = crate::merge_output_and_errors(&godbolt_result.output, &godbolt_result.stderr);
     let codeblock_lang = if godbolt_result.success {
@@ -311,12 +527,22 @@ pub async fn llvmir(
     } else {
         "rust"
     };
-    let note = if code.code.contains("pub fn") {
+    let note = if code.contains("pub fn") {
         ""
     } else {
         "Note: only public functions (`pub fn`) are shown"
     };
-    respond_codeblock(ctx, codeblock_lang, &text, &note, &godbolt_request).await?;
+    respond_codeblock(
+        ctx,
+        codeblock_lang,
+        &text,
+        &note,
+        &godbolt_request,
+        params
+            .overflow
+            .unwrap_or(crate::OverflowStrategy::Attachment),
+    )
+    .await?;
 
     Ok(())
 }
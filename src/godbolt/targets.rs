@@ -121,9 +121,9 @@ pub async fn fetch_godbolt_metadata(
 // Full list of version<->id can be obtained at https://godbolt.org/api/compilers/rust
 pub(super) async fn rustc_id_and_flags(
     data: &Data,
-    params: &poise::KeyValueArgs,
+    params: &super::GodboltParams<'_>,
 ) -> Result<(String, String), Error> {
-    let rustc = params.get("rustc").unwrap_or("nightly");
+    let rustc = params.rustc.unwrap_or("nightly");
     let target = fetch_godbolt_metadata(data).await.targets
         .iter().find(|target| target.semver == rustc.trim()).cloned()
         .ok_or(
@@ -132,13 +132,67 @@ pub(super) async fn rustc_id_and_flags(
         )?;
 
     let flags = params
-        .get("flags")
+        .flags
         .unwrap_or("-Copt-level=3 --edition=2021")
         .to_owned();
 
     Ok((target.id, flags))
 }
 
+/// Suggests rustc versions available on Godbolt for the `rustc` slash command option
+pub(super) async fn autocomplete_rustc(ctx: Context<'_>, partial: &str) -> Vec<String> {
+    let mut versions = fetch_godbolt_metadata(ctx.data())
+        .await
+        .targets
+        .iter()
+        .filter(|target| target.semver.starts_with(partial.trim()))
+        .map(|target| target.semver.clone())
+        .collect::<Vec<_>>();
+    versions.sort_unstable_by(|lhs, rhs| {
+        SemverRanking::from(lhs.as_str()).cmp(&SemverRanking::from(rhs.as_str()))
+    });
+    versions.dedup();
+    // Discord shows at most 25 suggestions
+    versions.truncate(25);
+    versions
+}
+
+/// A stable rustc release available on Godbolt
+pub(super) struct RustcRelease {
+    /// Major, minor and patch version
+    pub version: (u16, u16, u16),
+    /// Version as displayed to users, like `1.45.2`
+    pub semver: String,
+    /// Godbolt compiler ID, like `r1452`
+    pub id: String,
+}
+
+/// Lists the stable rustc releases available on Godbolt, oldest first
+pub(super) async fn rustc_releases(data: &Data) -> Vec<RustcRelease> {
+    let mut targets = fetch_godbolt_metadata(data)
+        .await
+        .targets
+        .iter()
+        .filter_map(|target| match SemverRanking::from(&*target.semver) {
+            SemverRanking::Semver(std::cmp::Reverse(version)) => Some((version, target.clone())),
+            _ => None,
+        })
+        .collect::<Vec<_>>();
+
+    // If a release is available for multiple instruction sets, prefer x86-64
+    targets.sort_by_key(|(version, target)| (*version, target.instruction_set != "amd64"));
+    targets.dedup_by_key(|(version, _)| *version);
+
+    targets
+        .into_iter()
+        .map(|(version, target)| RustcRelease {
+            version,
+            semver: target.semver,
+            id: target.id,
+        })
+        .collect()
+}
+
 /// Used to rank godbolt compiler versions for listing them out
 #[derive(PartialEq, Eq, PartialOrd, Ord)]
 enum SemverRanking<'a> {
@@ -197,20 +251,27 @@ pub async fn targets(ctx: Context<'_>) -> Result<(), Error> {
         SemverRanking::from(&*lhs.semver).cmp(&SemverRanking::from(&*rhs.semver))
     });
 
-    ctx.send(
+    // Discord allows at most 25 fields per embed
+    let pages = targets.chunks(25).collect::<Vec<_>>();
+    crate::paginate::paginate(ctx, pages.len().max(1), |i| {
         poise::CreateReply::new().embed(
             serenity::CreateEmbed::new()
                 .title("Godbolt Targets")
-                .fields(targets.into_iter().map(|target| {
-                    (
-                        target.semver,
-                        format!("{} (runs on {})", target.name, target.instruction_set),
-                        true,
-                    )
-                })),
-        ),
-    )
-    .await?;
-
-    Ok(())
+                .fields(
+                    pages
+                        .get(i)
+                        .copied()
+                        .unwrap_or_default()
+                        .iter()
+                        .map(|target| {
+                            (
+                                target.semver.clone(),
+                                format!("{} (runs on {})", target.name, target.instruction_set),
+                                true,
+                            )
+                        }),
+                ),
+        )
+    })
+    .await
 }
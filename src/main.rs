@@ -1,9 +1,12 @@
+mod cache;
 mod crates;
 mod godbolt;
 mod misc;
 mod moderation;
+mod paginate;
 mod playground;
 mod prefixes;
+mod ratelimit;
 mod showcase;
 
 use poise::serenity_prelude as serenity;
@@ -11,6 +14,12 @@ use poise::serenity_prelude as serenity;
 type Error = Box<dyn std::error::Error + Send + Sync>;
 type Context<'a> = poise::Context<'a, Data, Error>;
 
+/// Discord's character limit for messages
+const MAX_MESSAGE_LENGTH: usize = 2000;
+/// Output with more lines than this is truncated or split into pages, because it would clutter
+/// the channel otherwise
+const MAX_OUTPUT_LINES: usize = 45;
+
 // const EMBED_COLOR: (u8, u8, u8) = (0xf7, 0x4c, 0x00);
 const EMBED_COLOR: (u8, u8, u8) = (0xb7, 0x47, 0x00); // slightly less saturated
 
@@ -26,6 +35,43 @@ fn merge_output_and_errors<'a>(output: &'a str, errors: &'a str) -> std::borrow:
     }
 }
 
+/// Modal which slash commands show to ask for code, because slash command options can't contain
+/// multi-line text
+#[derive(Debug, poise::Modal)]
+#[name = "Enter code"]
+struct CodeModal {
+    #[name = "Code"]
+    #[placeholder = "fn main() {\n    println!(\"Hello, world!\");\n}"]
+    #[paragraph]
+    code: String,
+}
+
+/// Asks the user for the code in a modal. Must be the first response to the interaction
+async fn code_from_modal(ctx: Context<'_>) -> Result<String, Error> {
+    let ctx = match ctx {
+        Context::Application(ctx) => ctx,
+        Context::Prefix(_) => return Err("code modals only work in slash commands".into()),
+    };
+    let modal: CodeModal = poise::Modal::execute(ctx)
+        .await?
+        .ok_or("You didn't submit any code in time")?;
+    Ok(modal.code)
+}
+
+/// Merges a separately implemented slash command into a prefix command, which is the canonical one
+/// with all the attributes set correctly. Needed where the slash command takes different
+/// arguments, e.g. because it asks for code in a modal
+fn with_slash_command(
+    prefix_command: poise::Command<Data, Error>,
+    slash_command: poise::Command<Data, Error>,
+) -> poise::Command<Data, Error> {
+    poise::Command {
+        slash_action: slash_command.slash_action,
+        parameters: slash_command.parameters,
+        ..prefix_command
+    }
+}
+
 /// In prefix commands, react with a red cross emoji. In slash commands, respond with a short
 /// explanation.
 async fn acknowledge_fail(error: poise::FrameworkError<'_, Data, Error>) {
@@ -81,6 +127,32 @@ code here
         if let Err(e) = ctx.say(error.to_string()).await {
             log::warn!("{}", e)
         }
+    } else if let poise::FrameworkError::CommandCheckFailed {
+        ctx,
+        error: Some(error),
+    } = error
+    {
+        if let Err(e) = ctx.say(error.to_string()).await {
+            log::warn!("{}", e)
+        }
+    }
+}
+
+/// Rate limits the commands which send requests to external services, per user and category
+async fn check_rate_limit(ctx: Context<'_>) -> Result<bool, Error> {
+    let category = match ctx.command().category {
+        Some(category @ ("Playground" | "Godbolt")) => category,
+        _ => return Ok(true),
+    };
+
+    match ctx.data().rate_limiter.check(ctx.author().id, category) {
+        Ok(()) => Ok(true),
+        Err(retry_after) => Err(format!(
+            "You're sending {} commands too quickly. Try again in {} seconds",
+            category.to_lowercase(),
+            retry_after.as_secs() + 1,
+        )
+        .into()),
     }
 }
 
@@ -138,7 +210,15 @@ pub struct Data {
     beginner_channel: serenity::ChannelId,
     bot_start_time: std::time::Instant,
     http: reqwest::Client,
+    playground: Box<dyn playground::PlaygroundBackend>,
+    playground_crates: std::sync::Mutex<playground::PlaygroundCrates>,
+    playground_versions: std::sync::Mutex<playground::PlaygroundVersions>,
+    /// Limits how often a user may run the commands that send requests to external services
+    rate_limiter: ratelimit::RateLimiter,
+    playground_queue: ratelimit::ConcurrencyQueue,
+    godbolt_queue: ratelimit::ConcurrencyQueue,
     database: sqlx::SqlitePool,
+    result_cache: cache::ResultCache,
     godbolt_metadata: std::sync::Mutex<godbolt::GodboltMetadata>,
     active_slowmodes:
         std::sync::Mutex<std::collections::HashMap<serenity::ChannelId, ActiveSlowmode>>,
@@ -163,22 +243,56 @@ async fn app() -> Result<(), Error> {
     let beginner_channel = env_var("BEGINNER_CHANNEL_ID")?;
     let database_url = env_var::<String>("DATABASE_URL")?;
     let custom_prefixes = env_var("CUSTOM_PREFIXES")?;
+    let playground_url = env_var::<String>("PLAYGROUND_URL")
+        .ok()
+        .filter(|url| !url.is_empty())
+        .unwrap_or_else(|| "https://play.rust-lang.org".to_owned());
+    let local_playground = env_var::<String>("LOCAL_PLAYGROUND").unwrap_or_default();
+    let result_cache_ttl = std::time::Duration::from_secs(
+        env_var("RESULT_CACHE_TTL").unwrap_or(60 * 60 * 6), // 6 hours
+    );
+    let result_cache_max_entries = env_var("RESULT_CACHE_MAX_ENTRIES").unwrap_or(500);
+    let result_cache_persist = env_var("RESULT_CACHE_PERSIST").unwrap_or(false);
+    let rate_limit_uses = env_var("RATE_LIMIT_USES").unwrap_or(5);
+    let rate_limit_period =
+        std::time::Duration::from_secs(env_var("RATE_LIMIT_PERIOD").unwrap_or(60));
+    // A queue without slots would never let any request through
+    let playground_concurrency = env_var::<usize>("PLAYGROUND_CONCURRENCY")
+        .unwrap_or(4)
+        .max(1);
+    let godbolt_concurrency = env_var::<usize>("GODBOLT_CONCURRENCY").unwrap_or(2).max(1);
 
     let mut options = poise::FrameworkOptions {
         commands: vec![
-            playground::play(),
+            with_slash_command(playground::play(), playground::application_play()),
             playground::playwarn(),
-            playground::eval(),
-            playground::miri(),
-            playground::expand(),
-            playground::clippy(),
-            playground::fmt(),
-            playground::microbench(),
-            playground::procmacro(),
-            godbolt::godbolt(),
-            godbolt::mca(),
-            godbolt::llvmir(),
+            with_slash_command(playground::eval(), playground::application_eval()),
+            with_slash_command(playground::miri(), playground::application_miri()),
+            with_slash_command(playground::expand(), playground::application_expand()),
+            with_slash_command(playground::clippy(), playground::application_clippy()),
+            with_slash_command(playground::fmt(), playground::application_fmt()),
+            with_slash_command(playground::fix(), playground::application_fix()),
+            with_slash_command(playground::migrate(), playground::application_migrate()),
+            with_slash_command(
+                playground::microbench(),
+                playground::application_microbench(),
+            ),
+            with_slash_command(playground::procmacro(), playground::application_procmacro()),
+            playground::test(),
+            playground::compare(),
+            playground::mir(),
+            playground::hir(),
+            playground::wasm(),
+            playground::run_on_playground(),
+            playground::format_with_rustfmt(),
+            playground::playcrates(),
+            playground::versions(),
+            with_slash_command(godbolt::godbolt(), godbolt::application_godbolt()),
+            with_slash_command(godbolt::mca(), godbolt::application_mca()),
+            with_slash_command(godbolt::llvmir(), godbolt::application_llvmir()),
             godbolt::targets(),
+            godbolt::bisect(),
+            godbolt::msrv(),
             crates::crate_(),
             crates::doc(),
             moderation::cleanup(),
@@ -245,6 +359,7 @@ async fn app() -> Result<(), Error> {
                 }
             })
         },
+        command_check: Some(|ctx| Box::pin(check_rate_limit(ctx))),
         on_error: |error| Box::pin(on_error(error)),
         listener: |ctx, event, _framework, data| Box::pin(listener(ctx, event, data)),
         ..Default::default()
@@ -291,6 +406,22 @@ async fn app() -> Result<(), Error> {
         .user_data_setup(move |ctx, bot, _framework| {
             Box::pin(async move {
                 ctx.set_activity(Some(serenity::ActivityData::listening("?help")));
+                let http = reqwest::Client::new();
+                let http_playground =
+                    playground::HttpPlayground::new(http.clone(), &playground_url);
+                let playground: Box<dyn playground::PlaygroundBackend> = match &*local_playground {
+                    "only" => Box::new(playground::LocalPlayground::default()),
+                    "fallback" => Box::new(playground::FallbackPlayground::new(
+                        Box::new(http_playground),
+                        Box::new(playground::LocalPlayground::default()),
+                    )),
+                    _ => Box::new(http_playground),
+                };
+                let result_cache = cache::ResultCache::new(
+                    result_cache_ttl,
+                    result_cache_max_entries,
+                    result_cache_persist.then(|| database.clone()),
+                );
                 Ok(Data {
                     bot_user_id: bot.user.id,
                     mod_role_id,
@@ -299,8 +430,19 @@ async fn app() -> Result<(), Error> {
                     showcase_channel,
                     beginner_channel,
                     bot_start_time: std::time::Instant::now(),
-                    http: reqwest::Client::new(),
+                    playground,
+                    playground_crates: std::sync::Mutex::new(
+                        playground::PlaygroundCrates::default(),
+                    ),
+                    playground_versions: std::sync::Mutex::new(
+                        playground::PlaygroundVersions::default(),
+                    ),
+                    http,
+                    rate_limiter: ratelimit::RateLimiter::new(rate_limit_uses, rate_limit_period),
+                    playground_queue: ratelimit::ConcurrencyQueue::new(playground_concurrency),
+                    godbolt_queue: ratelimit::ConcurrencyQueue::new(godbolt_concurrency),
                     database,
+                    result_cache,
                     godbolt_metadata: std::sync::Mutex::new(godbolt::GodboltMetadata::default()),
                     active_slowmodes: std::sync::Mutex::new(std::collections::HashMap::new()),
                 })
@@ -367,6 +509,21 @@ async fn acknowledge_success(
     Ok(())
 }
 
+/// What to do with command output that doesn't fit into a single Discord message
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, poise::ChoiceParameter)]
+pub enum OverflowStrategy {
+    /// Truncate the output and link to the full output on an external site, like a playground gist
+    #[default]
+    #[name = "link"]
+    Link,
+    /// Truncate the output and attach the full output as files
+    #[name = "attachment"]
+    Attachment,
+    /// Split the output into pages which can be flipped through with buttons
+    #[name = "paginate"]
+    Paginate,
+}
+
 /// Truncates the message with a given truncation message if the
 /// text is too long. "Too long" means, it either goes beyond Discord's 2000 char message limit,
 /// or if the text_body has too many lines.
@@ -379,20 +536,18 @@ async fn trim_text(
     text_end: &str,
     truncation_msg_future: impl std::future::Future<Output = String>,
 ) -> String {
-    const MAX_OUTPUT_LINES: usize = 45;
-
     // Err with the future inside if no truncation occurs
     let mut truncation_msg_maybe = Err(truncation_msg_future);
 
     // check Discord's 2000 char message limit first
-    if text_body.len() + text_end.len() > 2000 {
+    if text_body.len() + text_end.len() > MAX_MESSAGE_LENGTH {
         let truncation_msg = match truncation_msg_maybe {
             Ok(msg) => msg,
             Err(future) => future.await,
         };
 
         // This is how long the text body may be at max to conform to Discord's limit
-        let available_space = 2000_usize
+        let available_space = MAX_MESSAGE_LENGTH
             .saturating_sub(text_end.len())
             .saturating_sub(truncation_msg.len());
 
@@ -428,15 +583,15 @@ async fn trim_text(
     }
 }
 
+/// Sends the text, split into multiple pages if it's too long. `text_start` and `text_end` are
+/// repeated on every page, see [`paginate::split_into_pages`]
 async fn reply_potentially_long_text(
     ctx: Context<'_>,
+    text_start: &str,
     text_body: &str,
     text_end: &str,
-    truncation_msg_future: impl std::future::Future<Output = String>,
 ) -> Result<(), Error> {
-    ctx.say(trim_text(text_body, text_end, truncation_msg_future).await)
-        .await?;
-    Ok(())
+    paginate::paginate_text(ctx, text_start, text_body, text_end).await
 }
 
 #[tokio::main]
@@ -1,5 +1,11 @@
 use crate::{serenity, Context, Error};
 
+/// Font for text in images that the bot renders
+pub static FONT: once_cell::sync::Lazy<rusttype::Font> = once_cell::sync::Lazy::new(|| {
+    rusttype::Font::try_from_bytes(include_bytes!("../assets/OpenSans.ttf"))
+        .expect("failed to load font")
+});
+
 /// Evaluates Go code
 #[poise::command(prefix_command, discard_spare_arguments, category = "Miscellaneous")]
 pub async fn go(ctx: Context<'_>) -> Result<(), Error> {
@@ -139,10 +145,6 @@ pub async fn conradluget(
         .decode()
         .expect("failed to load image")
     });
-    static FONT: Lazy<rusttype::Font> = Lazy::new(|| {
-        rusttype::Font::try_from_bytes(include_bytes!("../assets/OpenSans.ttf"))
-            .expect("failed to load font")
-    });
 
     let image = imageproc::drawing::draw_text(
         &*BASE_IMAGE,
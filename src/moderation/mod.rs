@@ -215,7 +215,6 @@ pub async fn report(
     prefix_command,
     slash_command,
     rename = "move",
-    aliases("migrate"),
     category = "Moderation"
 )]
 pub async fn move_(
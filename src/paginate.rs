@@ -0,0 +1,129 @@
+//! Splits output that's too long for a single message into pages, which the user can flip through
+//! with buttons
+
+use crate::{serenity, Context, Error};
+
+/// Splits `text_body` into pages that each fit into a Discord message together with `text_start`
+/// and `text_end`, which are repeated on every page (e.g. the opening and closing code block
+/// backticks). Always returns at least one page
+pub fn split_into_pages(text_start: &str, text_body: &str, text_end: &str) -> Vec<String> {
+    // Leave some room for the page body, even if start and end are unreasonably long
+    let available_space = crate::MAX_MESSAGE_LENGTH
+        .saturating_sub(text_start.len() + text_end.len())
+        .max(100);
+
+    let mut pages = Vec::new();
+    let mut page = String::new();
+    let mut page_lines = 0;
+    for mut line in text_body.split_inclusive('\n') {
+        while !line.is_empty() {
+            // Lines that don't fit into a page on their own are split
+            let mut line_end = line.len().min(available_space);
+            while !line.is_char_boundary(line_end) {
+                line_end -= 1;
+            }
+
+            if page.len() + line_end > available_space || page_lines >= crate::MAX_OUTPUT_LINES {
+                pages.push(std::mem::take(&mut page));
+                page_lines = 0;
+            }
+            page += &line[..line_end];
+            page_lines += 1;
+            line = &line[line_end..];
+        }
+    }
+    if !page.is_empty() || pages.is_empty() {
+        pages.push(page);
+    }
+
+    pages
+        .into_iter()
+        .map(|page| format!("{}{}{}", text_start, page, text_end))
+        .collect()
+}
+
+/// Sends the first page and adds buttons to flip through the pages. `page` is called with the page
+/// index to build the reply for that page. `page_count` must be at least one.
+///
+/// Once nobody has pressed a button for a while, the buttons are removed
+pub async fn paginate(
+    ctx: Context<'_>,
+    page_count: usize,
+    page: impl Fn(usize) -> poise::CreateReply,
+) -> Result<(), Error> {
+    if page_count <= 1 {
+        ctx.send(page(0)).await?;
+        return Ok(());
+    }
+
+    let prev_button_id = format!("{}prev", ctx.id());
+    let next_button_id = format!("{}next", ctx.id());
+    let buttons = |current_page: usize| {
+        vec![serenity::CreateActionRow::Buttons(vec![
+            serenity::CreateButton::new("◀", serenity::ButtonStyle::Secondary, &prev_button_id)
+                .disabled(current_page == 0),
+            serenity::CreateButton::new(
+                format!("{}/{}", current_page + 1, page_count),
+                serenity::ButtonStyle::Secondary,
+                format!("{}page", ctx.id()),
+            )
+            .disabled(true),
+            serenity::CreateButton::new("▶", serenity::ButtonStyle::Secondary, &next_button_id)
+                .disabled(current_page + 1 == page_count),
+        ])]
+    };
+
+    let mut current_page = 0;
+    let response = ctx
+        .send(page(current_page).components(buttons(current_page)))
+        .await?;
+    let message = response.message().await?;
+
+    loop {
+        let button_ids = [prev_button_id.clone(), next_button_id.clone()];
+        let press = message
+            .component_interaction_collector(&ctx.discord().shard)
+            .filter(std::sync::Arc::new(move |x| {
+                button_ids.contains(&x.data.custom_id)
+            }))
+            .timeout(std::time::Duration::from_secs(600))
+            .collect_single()
+            .await;
+        let press = match press {
+            Some(press) => press,
+            None => break,
+        };
+
+        if press.data.custom_id == next_button_id {
+            current_page = (current_page + 1).min(page_count - 1);
+        } else {
+            current_page = current_page.saturating_sub(1);
+        }
+
+        press.defer(ctx.discord()).await?;
+        response
+            .edit(ctx, page(current_page).components(buttons(current_page)))
+            .await?;
+    }
+
+    // If timed out, just remove the buttons
+    response
+        .edit(ctx, poise::CreateReply::new().components(vec![]))
+        .await?;
+
+    Ok(())
+}
+
+/// Like [`paginate`], but for plain text pages as returned by [`split_into_pages`]
+pub async fn paginate_text(
+    ctx: Context<'_>,
+    text_start: &str,
+    text_body: &str,
+    text_end: &str,
+) -> Result<(), Error> {
+    let pages = split_into_pages(text_start, text_body, text_end);
+    paginate(ctx, pages.len(), |i| {
+        poise::CreateReply::new().content(pages[i].clone())
+    })
+    .await
+}
@@ -1,16 +1,24 @@
 use crate::{Context, Error};
 
-use reqwest::header;
 use serde::{Deserialize, Deserializer, Serialize};
-use std::collections::HashMap;
 use std::str::FromStr;
 
+/// Flags shared by the playground commands. Prefix commands parse them from `key=value`
+/// arguments via `parse_flags`, slash commands receive them as typed options
+#[derive(Default)]
 pub struct CommandFlags {
     pub channel: Channel,
     pub mode: Mode,
     pub edition: Edition,
     pub warn: bool,
     pub run: bool,
+    /// None if the crate type should be detected from the code
+    pub crate_type: Option<CrateType>,
+    pub overflow: crate::OverflowStrategy,
+    /// Whether to show the exact rustc version below the output
+    pub version: bool,
+    /// Whether the result of an identical earlier execution may be reused
+    pub cache: bool,
 }
 
 #[derive(Debug, Serialize)]
@@ -46,7 +54,7 @@ pub struct FormatRequest<'a> {
     pub code: &'a str,
     pub edition: Edition,
 }
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Serialize, Deserialize)]
 pub struct FormatResponse {
     pub success: bool,
     pub code: String,
@@ -75,16 +83,26 @@ pub struct CompileRequest<'a> {
 pub enum AssemblyFlavour {
     #[default]
     Intel,
-    #[allow(dead_code)]
     Att,
 }
 
+impl FromStr for AssemblyFlavour {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Error> {
+        match s {
+            "intel" => Ok(AssemblyFlavour::Intel),
+            "att" => Ok(AssemblyFlavour::Att),
+            _ => Err(format!("invalid assembly flavor `{}`", s).into()),
+        }
+    }
+}
+
 #[derive(Debug, Default, Serialize)]
 #[serde(rename_all = "snake_case")]
 pub enum DemangleAssembly {
     #[default]
     Demangle,
-    #[allow(dead_code)]
     Mangle,
 }
 
@@ -93,90 +111,132 @@ pub enum DemangleAssembly {
 pub enum ProcessAssembly {
     #[default]
     Filter,
-    #[allow(dead_code)]
     Raw,
 }
 
-#[derive(Debug, Serialize)]
+#[derive(Debug, Clone, Copy, Serialize)]
 #[serde(rename_all = "snake_case")]
 pub enum CompileTarget {
+    #[allow(dead_code)]
+    Asm,
+    #[allow(dead_code)]
+    #[serde(rename = "llvm-ir")]
+    LlvmIr,
     Mir,
+    Hir,
+    Wasm,
 }
 
 pub type CompileResponse = FormatResponse;
 
-#[derive(Debug, Clone, Copy, Serialize)]
+#[derive(Debug, Default, Clone, Copy, Serialize, poise::ChoiceParameter)]
 #[serde(rename_all = "snake_case")]
 pub enum Channel {
+    #[name = "stable"]
     Stable,
+    #[name = "beta"]
     Beta,
+    #[default]
+    #[name = "nightly"]
     Nightly,
 }
 
-impl FromStr for Channel {
-    type Err = Error;
-
-    fn from_str(s: &str) -> Result<Self, Error> {
-        match s {
-            "stable" => Ok(Channel::Stable),
-            "beta" => Ok(Channel::Beta),
-            "nightly" => Ok(Channel::Nightly),
-            _ => Err(format!("invalid release channel `{}`", s).into()),
-        }
-    }
-}
-
-#[derive(Debug, Clone, Copy, Serialize)]
+#[derive(
+    Debug, Default, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, poise::ChoiceParameter,
+)]
 pub enum Edition {
     #[serde(rename = "2015")]
+    #[name = "2015"]
     E2015,
     #[serde(rename = "2018")]
+    #[name = "2018"]
     E2018,
+    #[default]
     #[serde(rename = "2021")]
+    #[name = "2021"]
     E2021,
 }
 
-impl FromStr for Edition {
-    type Err = Error;
-
-    fn from_str(s: &str) -> Result<Self, Error> {
-        match s {
-            "2015" => Ok(Edition::E2015),
-            "2018" => Ok(Edition::E2018),
-            "2021" => Ok(Edition::E2021),
-            _ => Err(format!("invalid edition `{}`", s).into()),
+impl Edition {
+    /// The edition that `cargo fix --edition` migrates to, if any
+    pub fn next(self) -> Option<Self> {
+        match self {
+            Self::E2015 => Some(Self::E2018),
+            Self::E2018 => Some(Self::E2021),
+            Self::E2021 => None,
         }
     }
 }
 
-#[derive(Debug, Clone, Copy, Serialize)]
+#[derive(Debug, Clone, Copy, Serialize, poise::ChoiceParameter)]
 pub enum CrateType {
     #[serde(rename = "bin")]
+    #[name = "bin"]
     Binary,
     #[serde(rename = "lib")]
+    #[name = "lib"]
     Library,
 }
 
-#[derive(Debug, Clone, Copy, Serialize)]
+#[derive(Debug, Default, Clone, Copy, Serialize, poise::ChoiceParameter)]
 #[serde(rename_all = "snake_case")]
 pub enum Mode {
+    #[default]
+    #[name = "debug"]
     Debug,
+    #[name = "release"]
     Release,
 }
 
-impl FromStr for Mode {
-    type Err = Error;
+/// A third-party crate that code on the playground can use
+#[derive(Debug, Clone, Deserialize)]
+pub struct CrateInfo {
+    pub name: String,
+    pub version: String,
+}
 
-    fn from_str(s: &str) -> Result<Self, Error> {
-        match s {
-            "debug" => Ok(Mode::Debug),
-            "release" => Ok(Mode::Release),
-            _ => Err(format!("invalid compilation mode `{}`", s).into()),
+#[derive(Debug, Deserialize)]
+pub struct CratesResponse {
+    pub crates: Vec<CrateInfo>,
+}
+
+/// Version of one tool of a toolchain, e.g. `1.75.0`, `82e1608dfa6e0b5569232559e3d385fea5a93112`
+/// and `2023-12-21`
+#[derive(Debug, Clone, Deserialize)]
+pub struct ToolVersion {
+    pub version: String,
+    pub hash: String,
+    pub date: String,
+}
+
+/// Tool versions of a release channel. Tools that aren't available are None
+#[derive(Debug, Clone, Deserialize)]
+pub struct ChannelVersions {
+    pub rustc: Option<ToolVersion>,
+    pub cargo: Option<ToolVersion>,
+    pub rustfmt: Option<ToolVersion>,
+    pub clippy: Option<ToolVersion>,
+    pub miri: Option<ToolVersion>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct VersionsResponse {
+    pub stable: ChannelVersions,
+    pub beta: ChannelVersions,
+    pub nightly: ChannelVersions,
+}
+
+impl VersionsResponse {
+    pub fn channel(&self, channel: Channel) -> &ChannelVersions {
+        match channel {
+            Channel::Stable => &self.stable,
+            Channel::Beta => &self.beta,
+            Channel::Nightly => &self.nightly,
         }
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Serialize)]
 pub struct PlayResult {
     pub success: bool,
     pub stdout: String,
@@ -220,61 +280,20 @@ impl<'de> Deserialize<'de> for PlayResult {
     }
 }
 
-/// Returns a gist ID
-pub async fn post_gist(ctx: Context<'_>, code: &str) -> Result<String, Error> {
-    let mut payload = HashMap::new();
-    payload.insert("code", code);
-
-    let resp = ctx
-        .data()
-        .http
-        .post("https://play.rust-lang.org/meta/gist/")
-        .header(header::REFERER, "https://discord.gg/rust-lang-community")
-        .json(&payload)
-        .send()
-        .await?;
-
-    let mut resp: HashMap<String, String> = resp.json().await?;
-    log::info!("gist response: {:?}", resp);
-
-    let gist_id = resp.remove("id").ok_or("no gist found")?;
-    Ok(gist_id)
-}
-
-pub fn url_from_gist(flags: &CommandFlags, gist_id: &str) -> String {
-    format!(
-        "https://play.rust-lang.org/?version={}&mode={}&edition={}&gist={}",
-        match flags.channel {
-            Channel::Nightly => "nightly",
-            Channel::Beta => "beta",
-            Channel::Stable => "stable",
-        },
-        match flags.mode {
-            Mode::Debug => "debug",
-            Mode::Release => "release",
-        },
-        match flags.edition {
-            Edition::E2015 => "2015",
-            Edition::E2018 => "2018",
-            Edition::E2021 => "2021",
-        },
-        gist_id
-    )
-}
-
 pub async fn apply_online_rustfmt(
     ctx: Context<'_>,
     code: &str,
     edition: Edition,
 ) -> Result<PlayResult, Error> {
+    let request = FormatRequest { code, edition };
     let result = ctx
         .data()
-        .http
-        .post("https://play.rust-lang.org/format")
-        .json(&FormatRequest { code, edition })
-        .send()
-        .await?
-        .json::<FormatResponse>()
+        .result_cache
+        .get_or_insert_with(
+            "format",
+            &request,
+            super::util::queued(ctx, ctx.data().playground.format(&request)),
+        )
         .await?;
 
     Ok(PlayResult {
@@ -0,0 +1,258 @@
+//! Abstraction over the service that actually compiles and runs the code
+
+use super::api::*;
+use crate::Error;
+
+use reqwest::header;
+use std::collections::HashMap;
+
+/// Future type returned by the [`PlaygroundBackend`] methods. We can't use async fns there
+/// because the backend is stored as a trait object
+pub type BackendFuture<'a, T> =
+    std::pin::Pin<Box<dyn std::future::Future<Output = Result<T, Error>> + Send + 'a>>;
+
+/// Something that can compile and run code for the playground commands. Usually that's
+/// play.rust-lang.org, but it may also be a self-hosted playground instance or an in-process fake
+pub trait PlaygroundBackend: std::fmt::Debug + Send + Sync {
+    fn execute<'a>(&'a self, request: &'a PlaygroundRequest<'a>) -> BackendFuture<'a, PlayResult>;
+
+    fn miri<'a>(&'a self, request: &'a MiriRequest<'a>) -> BackendFuture<'a, PlayResult>;
+
+    fn macro_expansion<'a>(
+        &'a self,
+        request: &'a MacroExpansionRequest<'a>,
+    ) -> BackendFuture<'a, PlayResult>;
+
+    fn clippy<'a>(&'a self, request: &'a ClippyRequest<'a>) -> BackendFuture<'a, PlayResult>;
+
+    fn format<'a>(&'a self, request: &'a FormatRequest<'a>) -> BackendFuture<'a, FormatResponse>;
+
+    fn compile<'a>(&'a self, request: &'a CompileRequest<'a>)
+        -> BackendFuture<'a, CompileResponse>;
+
+    /// Lists the third-party crates that code can use
+    fn crates(&self) -> BackendFuture<'_, Vec<CrateInfo>>;
+
+    /// Looks up the versions of rustc and the other tools of every release channel
+    fn versions(&self) -> BackendFuture<'_, VersionsResponse>;
+
+    /// Uploads the code and returns a link which opens it in the web interface of this playground
+    fn gist<'a>(&'a self, code: &'a str, flags: &'a CommandFlags) -> BackendFuture<'a, String>;
+}
+
+/// Talks to a playground instance over HTTP, like <https://play.rust-lang.org>
+#[derive(Debug)]
+pub struct HttpPlayground {
+    http: reqwest::Client,
+    /// Without trailing slash, e.g. `https://play.rust-lang.org`
+    base_url: String,
+}
+
+impl HttpPlayground {
+    pub fn new(http: reqwest::Client, base_url: &str) -> Self {
+        Self {
+            http,
+            base_url: base_url.trim_end_matches('/').to_owned(),
+        }
+    }
+
+    async fn post<T: serde::de::DeserializeOwned>(
+        &self,
+        endpoint: &str,
+        request: &(impl serde::Serialize + Sync),
+    ) -> Result<T, Error> {
+        Ok(self
+            .http
+            .post(format!("{}/{}", self.base_url, endpoint))
+            .json(request)
+            .send()
+            .await?
+            .json()
+            .await?)
+    }
+}
+
+impl PlaygroundBackend for HttpPlayground {
+    fn execute<'a>(&'a self, request: &'a PlaygroundRequest<'a>) -> BackendFuture<'a, PlayResult> {
+        Box::pin(self.post("execute", request))
+    }
+
+    fn miri<'a>(&'a self, request: &'a MiriRequest<'a>) -> BackendFuture<'a, PlayResult> {
+        Box::pin(self.post("miri", request))
+    }
+
+    fn macro_expansion<'a>(
+        &'a self,
+        request: &'a MacroExpansionRequest<'a>,
+    ) -> BackendFuture<'a, PlayResult> {
+        Box::pin(self.post("macro-expansion", request))
+    }
+
+    fn clippy<'a>(&'a self, request: &'a ClippyRequest<'a>) -> BackendFuture<'a, PlayResult> {
+        Box::pin(self.post("clippy", request))
+    }
+
+    fn format<'a>(&'a self, request: &'a FormatRequest<'a>) -> BackendFuture<'a, FormatResponse> {
+        Box::pin(self.post("format", request))
+    }
+
+    fn compile<'a>(
+        &'a self,
+        request: &'a CompileRequest<'a>,
+    ) -> BackendFuture<'a, CompileResponse> {
+        Box::pin(self.post("compile", request))
+    }
+
+    fn crates(&self) -> BackendFuture<'_, Vec<CrateInfo>> {
+        Box::pin(async move {
+            let response: CratesResponse = self
+                .http
+                .get(format!("{}/meta/crates", self.base_url))
+                .send()
+                .await?
+                .json()
+                .await?;
+            Ok(response.crates)
+        })
+    }
+
+    fn versions(&self) -> BackendFuture<'_, VersionsResponse> {
+        Box::pin(async move {
+            Ok(self
+                .http
+                .get(format!("{}/meta/versions", self.base_url))
+                .send()
+                .await?
+                .json()
+                .await?)
+        })
+    }
+
+    fn gist<'a>(&'a self, code: &'a str, flags: &'a CommandFlags) -> BackendFuture<'a, String> {
+        Box::pin(async move {
+            let mut payload = HashMap::new();
+            payload.insert("code", code);
+
+            let resp = self
+                .http
+                .post(format!("{}/meta/gist/", self.base_url))
+                .header(header::REFERER, "https://discord.gg/rust-lang-community")
+                .json(&payload)
+                .send()
+                .await?;
+
+            let mut resp: HashMap<String, String> = resp.json().await?;
+            log::info!("gist response: {:?}", resp);
+
+            let gist_id = resp.remove("id").ok_or("no gist found")?;
+            Ok(format!(
+                "{}/?version={}&mode={}&edition={}&gist={}",
+                self.base_url,
+                match flags.channel {
+                    Channel::Nightly => "nightly",
+                    Channel::Beta => "beta",
+                    Channel::Stable => "stable",
+                },
+                match flags.mode {
+                    Mode::Debug => "debug",
+                    Mode::Release => "release",
+                },
+                match flags.edition {
+                    Edition::E2015 => "2015",
+                    Edition::E2018 => "2018",
+                    Edition::E2021 => "2021",
+                },
+                gist_id
+            ))
+        })
+    }
+}
+
+/// Uses the primary backend, and falls back to the secondary backend when the primary one fails,
+/// e.g. because play.rust-lang.org is down
+#[derive(Debug)]
+pub struct FallbackPlayground {
+    primary: Box<dyn PlaygroundBackend>,
+    fallback: Box<dyn PlaygroundBackend>,
+}
+
+impl FallbackPlayground {
+    pub fn new(primary: Box<dyn PlaygroundBackend>, fallback: Box<dyn PlaygroundBackend>) -> Self {
+        Self { primary, fallback }
+    }
+}
+
+async fn with_fallback<'a, T>(
+    primary: BackendFuture<'a, T>,
+    fallback: impl FnOnce() -> BackendFuture<'a, T>,
+) -> Result<T, Error> {
+    match primary.await {
+        Ok(response) => Ok(response),
+        Err(e) => {
+            log::warn!("playground backend failed, using fallback: {}", e);
+            fallback().await
+        }
+    }
+}
+
+impl PlaygroundBackend for FallbackPlayground {
+    fn execute<'a>(&'a self, request: &'a PlaygroundRequest<'a>) -> BackendFuture<'a, PlayResult> {
+        Box::pin(with_fallback(self.primary.execute(request), move || {
+            self.fallback.execute(request)
+        }))
+    }
+
+    fn miri<'a>(&'a self, request: &'a MiriRequest<'a>) -> BackendFuture<'a, PlayResult> {
+        Box::pin(with_fallback(self.primary.miri(request), move || {
+            self.fallback.miri(request)
+        }))
+    }
+
+    fn macro_expansion<'a>(
+        &'a self,
+        request: &'a MacroExpansionRequest<'a>,
+    ) -> BackendFuture<'a, PlayResult> {
+        Box::pin(with_fallback(
+            self.primary.macro_expansion(request),
+            move || self.fallback.macro_expansion(request),
+        ))
+    }
+
+    fn clippy<'a>(&'a self, request: &'a ClippyRequest<'a>) -> BackendFuture<'a, PlayResult> {
+        Box::pin(with_fallback(self.primary.clippy(request), move || {
+            self.fallback.clippy(request)
+        }))
+    }
+
+    fn format<'a>(&'a self, request: &'a FormatRequest<'a>) -> BackendFuture<'a, FormatResponse> {
+        Box::pin(with_fallback(self.primary.format(request), move || {
+            self.fallback.format(request)
+        }))
+    }
+
+    fn compile<'a>(
+        &'a self,
+        request: &'a CompileRequest<'a>,
+    ) -> BackendFuture<'a, CompileResponse> {
+        Box::pin(with_fallback(self.primary.compile(request), move || {
+            self.fallback.compile(request)
+        }))
+    }
+
+    fn crates(&self) -> BackendFuture<'_, Vec<CrateInfo>> {
+        Box::pin(with_fallback(self.primary.crates(), move || {
+            self.fallback.crates()
+        }))
+    }
+
+    fn versions(&self) -> BackendFuture<'_, VersionsResponse> {
+        Box::pin(with_fallback(self.primary.versions(), move || {
+            self.fallback.versions()
+        }))
+    }
+
+    fn gist<'a>(&'a self, code: &'a str, flags: &'a CommandFlags) -> BackendFuture<'a, String> {
+        // Gists only exist on the backend that created them, so no fallback here
+        self.primary.gist(code, flags)
+    }
+}
@@ -0,0 +1,190 @@
+use super::{api::*, playcrates::apply_dependency_header, util::*};
+use crate::{serenity, Context, Error};
+
+/// Most configurations that a single ?compare invocation may run, to not hog the playground
+const MAX_CONFIGURATIONS: usize = 8;
+
+/// Run code with multiple channels, editions or modes and compare the output
+#[poise::command(
+    prefix_command,
+    track_edits,
+    help_text_fn = "compare_help",
+    category = "Playground"
+)]
+pub async fn compare(
+    ctx: Context<'_>,
+    mut flags: poise::KeyValueArgs,
+    code: Option<poise::CodeBlock>,
+) -> Result<(), Error> {
+    let code = resolve_code(ctx, code).await?;
+
+    let mut flag_parse_errors = String::new();
+    let channels = pop_flag_list(&mut flags, "channel", &mut flag_parse_errors);
+    let editions = pop_flag_list(&mut flags, "edition", &mut flag_parse_errors);
+    let modes = pop_flag_list(&mut flags, "mode", &mut flag_parse_errors);
+    let (flags, remaining_flag_parse_errors) = parse_flags(flags);
+    flag_parse_errors += &remaining_flag_parse_errors;
+
+    let channels = if channels.is_empty() {
+        vec![flags.channel]
+    } else {
+        channels
+    };
+    let editions = if editions.is_empty() {
+        vec![flags.edition]
+    } else {
+        editions
+    };
+    let modes = if modes.is_empty() {
+        vec![flags.mode]
+    } else {
+        modes
+    };
+
+    let configuration_count = channels.len() * editions.len() * modes.len();
+    if configuration_count < 2 {
+        return Err(
+            "Pass multiple comma-separated values to `channel`, `edition` or `mode` to \
+            compare them, for example `channel=stable,nightly`"
+                .into(),
+        );
+    }
+    if configuration_count > MAX_CONFIGURATIONS {
+        return Err(format!(
+            "That's {} configurations, but at most {} can be compared at once",
+            configuration_count, MAX_CONFIGURATIONS
+        )
+        .into());
+    }
+
+    send_stub_message(ctx).await?;
+
+    let code = apply_dependency_header(ctx, code, &mut flag_parse_errors).await?;
+    let crate_type = crate_type(&flags, &code);
+    let code = maybe_wrap_for_crate_type(&code, crate_type, ResultHandling::None);
+
+    // Only the values that differ between configurations are worth mentioning in the labels
+    let mut configurations = Vec::new();
+    for &channel in &channels {
+        for &edition in &editions {
+            for &mode in &modes {
+                let mut label = Vec::new();
+                if channels.len() > 1 {
+                    label.push(format!("channel={}", channel.name()));
+                }
+                if editions.len() > 1 {
+                    label.push(format!("edition={}", edition.name()));
+                }
+                if modes.len() > 1 {
+                    label.push(format!("mode={}", mode.name()));
+                }
+
+                let request = PlaygroundRequest {
+                    code: &code,
+                    channel,
+                    crate_type,
+                    edition,
+                    mode,
+                    tests: false,
+                };
+                configurations.push((label.join(" "), request));
+            }
+        }
+    }
+
+    // Every configuration waits for its own slot in the queue, so that a comparison doesn't take
+    // up more of the playground than other commands
+    let playground = &ctx.data().playground;
+    let results = serenity::futures::future::join_all(
+        configurations
+            .iter()
+            .map(|(_, request)| queued(ctx, playground.execute(request))),
+    )
+    .await;
+
+    // Configurations with identical output are collapsed into one group
+    let mut groups: Vec<(String, Vec<&str>)> = Vec::new();
+    for ((label, _), result) in configurations.iter().zip(results) {
+        let output = match result {
+            Ok(result) => crate::merge_output_and_errors(
+                &result.stdout,
+                &format_play_eval_stderr(&result.stderr, flags.warn),
+            )
+            .into_owned(),
+            Err(e) => format!("Error: {}", e),
+        };
+        match groups
+            .iter_mut()
+            .find(|(other_output, _)| *other_output == output)
+        {
+            Some((_, labels)) => labels.push(label.as_str()),
+            None => groups.push((output, vec![label.as_str()])),
+        }
+    }
+
+    let sections = groups
+        .iter()
+        .map(|(output, labels)| {
+            let header = if groups.len() == 1 {
+                "All configurations produce the same output".to_owned()
+            } else {
+                labels.join(", ")
+            };
+            // Rendered compiler diagnostics contain ANSI escape codes
+            let language = if output.contains('\x1b') {
+                "ansi"
+            } else {
+                "rust"
+            };
+            format!("**{}**\n```{}\n{}", header, language, output)
+        })
+        .collect::<Vec<_>>();
+
+    // Send everything in one message if possible, otherwise one page per group
+    let single_message = sections
+        .iter()
+        .fold(flag_parse_errors.clone(), |text, section| {
+            text + section + "\n```\n"
+        });
+    if single_message.len() <= crate::MAX_MESSAGE_LENGTH
+        && single_message.lines().count() <= crate::MAX_OUTPUT_LINES
+    {
+        ctx.say(single_message).await?;
+        return Ok(());
+    }
+
+    let mut pages = Vec::new();
+    for section in &sections {
+        let page = crate::trim_text(
+            &format!("{}{}", flag_parse_errors, section),
+            "\n```",
+            async { "Output too large".to_owned() },
+        )
+        .await;
+        pages.push(page);
+    }
+    crate::paginate::paginate(ctx, pages.len(), |i| {
+        poise::CreateReply::new().content(pages[i].clone())
+    })
+    .await
+}
+
+pub fn compare_help() -> String {
+    let mut help = generic_help(GenericHelp {
+        command: "compare",
+        desc: "Run code with multiple release channels, editions or compilation modes at once and \
+        compare the output. Pass comma-separated values to `channel`, `edition` or `mode`, for \
+        example `channel=stable,beta,nightly`. Configurations with identical output are grouped \
+        together",
+        mode_and_channel: true,
+        warn: true,
+        run: false,
+        crate_type: true,
+        example_code: "code",
+    });
+    help += &format!(
+        "At most {} configurations can be compared at once\n",
+        MAX_CONFIGURATIONS
+    );
+    help
+}
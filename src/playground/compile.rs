@@ -0,0 +1,152 @@
+use super::{api::*, playcrates::apply_dependency_header, util::*};
+use crate::{Context, Error};
+
+/// Shared implementation of the commands that show an intermediate compiler output
+async fn compile_and_reply(
+    ctx: Context<'_>,
+    mut flags: poise::KeyValueArgs,
+    code: Option<poise::CodeBlock>,
+    target: CompileTarget,
+) -> Result<(), Error> {
+    let code = resolve_code(ctx, code).await?;
+    send_stub_message(ctx).await?;
+
+    let mut flag_parse_errors = String::new();
+    let assembly_flavor =
+        pop_flag(&mut flags, "flavor", &mut flag_parse_errors).unwrap_or_default();
+    let demangle_assembly = match pop_flag(&mut flags, "demangle", &mut flag_parse_errors) {
+        Some(false) => DemangleAssembly::Mangle,
+        _ => DemangleAssembly::Demangle,
+    };
+    let process_assembly = match pop_flag(&mut flags, "filter", &mut flag_parse_errors) {
+        Some(false) => ProcessAssembly::Raw,
+        _ => ProcessAssembly::Filter,
+    };
+    let (mut flags, other_flag_parse_errors) = parse_flags(flags);
+    flag_parse_errors += &other_flag_parse_errors;
+    let code = apply_dependency_header(ctx, code, &mut flag_parse_errors).await?;
+
+    // The playground only supports these targets on nightly
+    if matches!(target, CompileTarget::Hir | CompileTarget::Wasm) {
+        flags.channel = Channel::Nightly;
+    }
+
+    let crate_type = crate_type(&flags, &code);
+    let code = maybe_wrap_for_crate_type(&code, crate_type, ResultHandling::None);
+
+    let request = CompileRequest {
+        assembly_flavor,
+        backtrace: false,
+        channel: flags.channel,
+        code: &code,
+        crate_type,
+        demangle_assembly,
+        edition: flags.edition,
+        mode: flags.mode,
+        process_assembly,
+        target,
+        tests: false,
+    };
+    let result = ctx
+        .data()
+        .result_cache
+        .get_or_insert_with(
+            "compile",
+            &request,
+            queued(ctx, ctx.data().playground.compile(&request)),
+        )
+        .await?;
+
+    let result = PlayResult {
+        success: result.success,
+        stdout: result.code,
+        stderr: format_play_eval_stderr(&result.stderr, flags.warn),
+    };
+
+    send_reply(ctx, result, &code, &flags, &flag_parse_errors).await
+}
+
+fn compile_help(command: &str, desc: &str) -> String {
+    let mut reply = generic_help(GenericHelp {
+        command,
+        desc,
+        mode_and_channel: true,
+        warn: true,
+        run: false,
+        crate_type: true,
+        example_code: "code",
+    });
+    reply += "- flavor: intel, att (default: intel)\n";
+    reply += "- demangle: true, false (default: true)\n";
+    reply += "- filter: true, false (default: true)\n";
+    reply
+}
+
+/// Show the MIR (mid-level intermediate representation) of Rust code
+#[poise::command(
+    prefix_command,
+    track_edits,
+    help_text_fn = "mir_help",
+    category = "Playground"
+)]
+pub async fn mir(
+    ctx: Context<'_>,
+    flags: poise::KeyValueArgs,
+    code: Option<poise::CodeBlock>,
+) -> Result<(), Error> {
+    compile_and_reply(ctx, flags, code, CompileTarget::Mir).await
+}
+
+pub fn mir_help() -> String {
+    compile_help(
+        "mir",
+        "Show the MIR (mid-level intermediate representation) that the compiler generates for the \
+        code",
+    )
+}
+
+/// Show the HIR (high-level intermediate representation) of Rust code
+#[poise::command(
+    prefix_command,
+    track_edits,
+    help_text_fn = "hir_help",
+    category = "Playground"
+)]
+pub async fn hir(
+    ctx: Context<'_>,
+    flags: poise::KeyValueArgs,
+    code: Option<poise::CodeBlock>,
+) -> Result<(), Error> {
+    compile_and_reply(ctx, flags, code, CompileTarget::Hir).await
+}
+
+pub fn hir_help() -> String {
+    compile_help(
+        "hir",
+        "Show the HIR (high-level intermediate representation) that the compiler generates for \
+        the code. Always uses the nightly channel",
+    )
+}
+
+/// Compile Rust code to WebAssembly and show the output
+#[poise::command(
+    prefix_command,
+    track_edits,
+    help_text_fn = "wasm_help",
+    category = "Playground"
+)]
+pub async fn wasm(
+    ctx: Context<'_>,
+    flags: poise::KeyValueArgs,
+    code: Option<poise::CodeBlock>,
+) -> Result<(), Error> {
+    compile_and_reply(ctx, flags, code, CompileTarget::Wasm).await
+}
+
+pub fn wasm_help() -> String {
+    compile_help(
+        "wasm",
+        "Compile the code to WebAssembly and show the generated module in text format. Always \
+        uses the nightly channel",
+    )
+}
@@ -0,0 +1,387 @@
+//! Parses the diagnostics in rustc's human-readable output and renders them with ANSI colors.
+//!
+//! We deliberately don't match on cargo's summary messages ("could not compile", "warnings
+//! emitted" etc.) because their wording changes every now and then. Instead, we rely on the
+//! structure of the output: cargo status lines are right-aligned to a fixed width, and rustc
+//! diagnostics are blocks separated by empty lines which start with a `level[code]: message` line
+
+/// ANSI SGR parameters for line numbers, arrows and secondary labels, like in rustc
+const GUTTER_STYLE: &str = "1;34";
+
+/// Severity of a diagnostic
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Level {
+    Error,
+    Warning,
+    Note,
+    Help,
+}
+
+impl Level {
+    fn parse(level: &str) -> Option<Self> {
+        Some(match level {
+            "error" => Self::Error,
+            "warning" => Self::Warning,
+            "note" => Self::Note,
+            "help" => Self::Help,
+            _ => return None,
+        })
+    }
+
+    /// ANSI SGR parameters that rustc uses for this level
+    fn ansi_style(self) -> &'static str {
+        match self {
+            Self::Error => "1;31",
+            Self::Warning => "1;33",
+            Self::Note => "1;32",
+            Self::Help => "1;36",
+        }
+    }
+}
+
+/// A line inside a diagnostic, below the `level[code]: message` header
+#[derive(Debug)]
+pub enum Line<'a> {
+    /// Location the diagnostic points at, like ` --> src/main.rs:2:9`
+    Span {
+        /// Everything up to and including the arrow (`-->` or `:::`)
+        arrow: &'a str,
+        location: &'a str,
+    },
+    /// Attached note, either indented like `  = note: ...` or as a sub-diagnostic like `note: ...`
+    Note {
+        /// Everything before the level, e.g. `  = `
+        prefix: &'a str,
+        level: Level,
+        message: &'a str,
+    },
+    /// Source code excerpt with line numbers and markers, or continuation line of a note
+    Snippet(&'a str),
+}
+
+/// A single compiler error, warning or the like, parsed out of rustc's human-readable output
+#[derive(Debug)]
+pub struct Diagnostic<'a> {
+    pub level: Level,
+    /// Error code like `E0502`, if any
+    pub code: Option<&'a str>,
+    pub message: &'a str,
+    pub lines: Vec<Line<'a>>,
+}
+
+impl Diagnostic<'_> {
+    /// Renders the diagnostic similar to how rustc does with `--color=always`. The returned
+    /// string has a trailing newline
+    pub fn render_ansi(&self) -> String {
+        let mut output = paint(self.level.ansi_style(), &self.header_level());
+        output += &paint("1", &format!(": {}", self.message));
+        output.push('\n');
+
+        for line in &self.lines {
+            match *line {
+                Line::Span { arrow, location } => {
+                    output += &paint(GUTTER_STYLE, arrow);
+                    output += " ";
+                    output += location;
+                }
+                Line::Note {
+                    prefix,
+                    level,
+                    message,
+                } => {
+                    output += &paint(GUTTER_STYLE, prefix);
+                    output += &paint(level.ansi_style(), &format!("{:?}", level).to_lowercase());
+                    output += ": ";
+                    output += message;
+                }
+                Line::Snippet(line) => output += &self.render_snippet_line(line),
+            }
+            output.push('\n');
+        }
+
+        output
+    }
+
+    /// `error[E0502]`, `warning` etc.
+    fn header_level(&self) -> String {
+        let level = format!("{:?}", self.level).to_lowercase();
+        match self.code {
+            Some(code) => format!("{}[{}]", level, code),
+            None => level,
+        }
+    }
+
+    fn render_snippet_line(&self, line: &str) -> String {
+        // Split off the gutter, i.e. the line number and the vertical bar
+        let (gutter, content) = match line.find('|') {
+            Some(bar) if line[..bar].trim().chars().all(|c| c.is_ascii_digit()) => {
+                line.split_at(bar + 1)
+            }
+            _ => ("", line),
+        };
+
+        // Marker lines underneath the source code are colored like rustc does: primary labels
+        // (`^^^`) in the level color, secondary labels (`---`) in the gutter color
+        let trimmed = content.trim_start_matches(|c| c == ' ' || c == '|' || c == '_');
+        let style = if trimmed.starts_with('^') {
+            Some(self.level.ansi_style())
+        } else if trimmed.starts_with('-') && !trimmed.starts_with("->") {
+            Some(GUTTER_STYLE)
+        } else {
+            None
+        };
+
+        let mut output = paint(GUTTER_STYLE, gutter);
+        match style {
+            Some(style) => output += &paint(style, content),
+            None => output += content,
+        }
+        output
+    }
+}
+
+fn paint(style: &str, text: &str) -> String {
+    if text.trim().is_empty() {
+        return text.to_owned();
+    }
+    format!("\x1b[{}m{}\x1b[0m", style, text)
+}
+
+/// Parses a `level[code]: message` line, where level is one of [`Level`]
+fn parse_header(line: &str) -> Option<(Level, Option<&str>, &str)> {
+    let (level_and_code, message) = line.split_once(": ")?;
+    let (level, code) = match level_and_code.split_once('[') {
+        Some((level, code)) => (level, Some(code.strip_suffix(']')?)),
+        None => (level_and_code, None),
+    };
+    Some((Level::parse(level)?, code, message))
+}
+
+/// Parses a single line of a diagnostic body
+fn parse_line(line: &str) -> Line<'_> {
+    let trimmed = line.trim_start();
+    let indent = &line[..(line.len() - trimmed.len())];
+
+    for arrow in ["-->", ":::"] {
+        if let Some(location) = trimmed.strip_prefix(arrow) {
+            return Line::Span {
+                arrow: &line[..(indent.len() + arrow.len())],
+                location: location.trim(),
+            };
+        }
+    }
+
+    let (prefix, note) = match trimmed.strip_prefix("= ") {
+        Some(note) => (&line[..(line.len() - note.len())], note),
+        None => (indent, trimmed),
+    };
+    if let Some((level @ (Level::Note | Level::Help), None, message)) = parse_header(note) {
+        return Line::Note {
+            prefix,
+            level,
+            message,
+        };
+    }
+
+    Line::Snippet(line)
+}
+
+/// Whether this is one of cargo's right-aligned status lines like `   Compiling playground`
+fn is_cargo_status_line(line: &str) -> bool {
+    const STATUS_WIDTH: usize = 12;
+
+    let (status, rest) = match line.get(..STATUS_WIDTH) {
+        Some(status) => (status.trim_start(), &line[STATUS_WIDTH..]),
+        None => return false,
+    };
+    line.starts_with(' ')
+        && rest.starts_with(' ')
+        && status.starts_with(|c: char| c.is_ascii_uppercase())
+        && status.chars().all(|c| c.is_ascii_alphabetic() || c == '-')
+}
+
+/// Extracts all diagnostics from the given text. Diagnostics without any body, like cargo's and
+/// rustc's summary messages ("aborting due to previous error"), are skipped.
+///
+/// Returns the diagnostics and the remaining lines that aren't part of any diagnostic. Cargo
+/// status lines are discarded
+pub fn parse_diagnostics(text: &str) -> (Vec<Diagnostic<'_>>, String) {
+    let mut diagnostics = Vec::new();
+    let mut other_lines = String::new();
+
+    let mut current: Option<Diagnostic<'_>> = None;
+    for line in text.lines() {
+        if line.trim().is_empty() || is_cargo_status_line(line) {
+            diagnostics.extend(current.take());
+            continue;
+        }
+
+        match parse_header(line) {
+            // Errors and warnings always start a new diagnostic. Notes and help messages that
+            // directly follow a diagnostic are sub-diagnostics and belong to it
+            Some((level, code, message))
+                if current.is_none() || matches!(level, Level::Error | Level::Warning) =>
+            {
+                diagnostics.extend(current.take());
+                current = Some(Diagnostic {
+                    level,
+                    code,
+                    message,
+                    lines: Vec::new(),
+                });
+            }
+            _ => match &mut current {
+                Some(diagnostic) => diagnostic.lines.push(parse_line(line)),
+                None => {
+                    other_lines.push_str(line);
+                    other_lines.push('\n');
+                }
+            },
+        }
+    }
+    diagnostics.extend(current);
+
+    diagnostics.retain(|diagnostic| !diagnostic.lines.is_empty());
+    (diagnostics, other_lines)
+}
+
+/// Renders the diagnostics with ANSI colors, separated by empty lines. The returned string has a
+/// trailing newline (except if empty)
+pub fn render_diagnostics(diagnostics: &[Diagnostic<'_>]) -> String {
+    diagnostics
+        .iter()
+        .map(|diagnostic| diagnostic.render_ansi())
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+/// Output of a cargo invocation, split into what the compiler and what the program printed
+pub struct CargoOutput<'a> {
+    /// Diagnostics that the compiler emitted
+    pub diagnostics: Vec<Diagnostic<'a>>,
+    /// Whether compilation finished successfully
+    pub compiled: bool,
+    /// What the program wrote to stderr, if it was run. Has a trailing newline (except if empty)
+    pub program_stderr: String,
+}
+
+/// Splits cargo's stderr into compiler diagnostics and the program's own stderr output
+pub fn parse_cargo_output(stderr: &str) -> CargoOutput<'_> {
+    let mut compiled = false;
+    let mut program_start = None;
+    let mut offset = 0;
+    for line in stderr.split_inclusive('\n') {
+        offset += line.len();
+        if is_cargo_status_line(line) {
+            match line.trim_start().split(' ').next() {
+                Some("Finished") => compiled = true,
+                Some("Running") => {
+                    program_start = Some(offset);
+                    break;
+                }
+                _ => {}
+            }
+        }
+    }
+
+    let (compiler_output, program_stderr) = match program_start {
+        Some(program_start) => stderr.split_at(program_start),
+        None => (stderr, ""),
+    };
+
+    // When running tests, cargo prints more status lines between the test binaries. Anything
+    // else is the program's own output, even if it happens to look like a status line
+    let program_stderr = program_stderr
+        .lines()
+        .filter(|line| {
+            !(is_cargo_status_line(line)
+                && matches!(
+                    line.trim_start().split(' ').next(),
+                    Some("Running" | "Doc-tests")
+                ))
+        })
+        .map(|line| format!("{}\n", line))
+        .collect::<String>();
+
+    CargoOutput {
+        diagnostics: parse_diagnostics(compiler_output).0,
+        compiled,
+        program_stderr: program_stderr.trim_start_matches('\n').to_owned(),
+    }
+}
+
+/// Turns the error codes mentioned in the given compiler output into Markdown links to the error
+/// code index. Returns an empty string if there are none
+pub fn error_code_links(stderr: &str) -> String {
+    let mut codes = Vec::new();
+    for (start, _) in stderr.match_indices("[E") {
+        let code = match stderr.get((start + 1)..(start + 6)) {
+            Some(code) => code,
+            None => continue,
+        };
+        if code[1..].chars().all(|c| c.is_ascii_digit())
+            && stderr[(start + 6)..].starts_with(']')
+            && !codes.contains(&code)
+        {
+            codes.push(code);
+        }
+    }
+
+    codes
+        .iter()
+        .map(|code| {
+            format!(
+                "[{0}](<https://doc.rust-lang.org/error_codes/{0}.html>)",
+                code
+            )
+        })
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+/// Lint links beyond this many are left out, so that they don't push the actual output out of the
+/// message
+const MAX_LINT_LINKS: usize = 10;
+
+/// Turns the Clippy lints mentioned in the given compiler output into Markdown links to their
+/// documentation. Returns an empty string if there are none
+pub fn clippy_lint_links(stderr: &str) -> String {
+    // Every Clippy diagnostic ends with a "for further information visit <url>#lint_name" line
+    let mut lint_urls = Vec::new();
+    for word in stderr.split_whitespace() {
+        let link = match word.split_once("/index.html#") {
+            Some((_, lint)) if word.contains("rust-clippy/") && !lint.is_empty() => (word, lint),
+            _ => continue,
+        };
+        if !lint_urls.contains(&link) {
+            lint_urls.push(link);
+        }
+    }
+
+    let mut links = lint_urls
+        .iter()
+        .take(MAX_LINT_LINKS)
+        .map(|(url, lint)| format!("[{}](<{}>)", lint, url))
+        .collect::<Vec<_>>();
+    if lint_urls.len() > MAX_LINT_LINKS {
+        links.push(format!("and {} more", lint_urls.len() - MAX_LINT_LINKS));
+    }
+    links.join(", ")
+}
+
+/// Removes the ANSI escape codes added by [`render_diagnostics`], for places where they can't be
+/// displayed, like file attachments
+pub fn strip_ansi_codes(text: &str) -> String {
+    let mut output = String::with_capacity(text.len());
+    let mut rest = text;
+    while let Some(escape_start) = rest.find("\x1b[") {
+        output.push_str(&rest[..escape_start]);
+        rest = &rest[escape_start..];
+        rest = match rest.find('m') {
+            Some(escape_end) => &rest[(escape_end + 1)..],
+            None => "",
+        };
+    }
+    output.push_str(rest);
+    output
+}
@@ -0,0 +1,439 @@
+use super::{api::*, playcrates::apply_dependency_header, util::*};
+use crate::{Context, Error, OverflowStrategy};
+use std::borrow::Cow;
+
+/// Apply the automatic fixes suggested by Clippy or rustc
+#[poise::command(
+    prefix_command,
+    track_edits,
+    help_text_fn = "fix_help",
+    category = "Playground"
+)]
+pub async fn fix(
+    ctx: Context<'_>,
+    mut flags: poise::KeyValueArgs,
+    code: Option<poise::CodeBlock>,
+) -> Result<(), Error> {
+    let code = resolve_code(ctx, code).await?;
+    let mut flag_parse_errors = String::new();
+    let tool = pop_flag(&mut flags, "tool", &mut flag_parse_errors).unwrap_or(FixTool::Clippy);
+    let (flags, other_flag_parse_errors) = parse_flags(flags);
+    flag_parse_errors += &other_flag_parse_errors;
+    fix_inner(ctx, flags, flag_parse_errors, tool, code).await
+}
+
+/// Apply the automatic fixes suggested by Clippy or rustc
+#[poise::command(slash_command)]
+pub async fn application_fix(
+    ctx: Context<'_>,
+    #[description = "Which tool's suggestions to apply. Clippy by default"] tool: Option<FixTool>,
+    #[description = "Rust edition"] edition: Option<Edition>,
+    #[description = "Crate type. Detected from the code by default"]
+    #[rename = "crate"]
+    crate_type: Option<CrateType>,
+    #[description = "How to send long output"] overflow: Option<OverflowStrategy>,
+) -> Result<(), Error> {
+    let code = crate::code_from_modal(ctx).await?;
+    let flags = CommandFlags {
+        edition: edition.unwrap_or_default(),
+        crate_type,
+        overflow: overflow.unwrap_or_default(),
+        ..Default::default()
+    };
+    let tool = tool.unwrap_or(FixTool::Clippy);
+    fix_inner(ctx, flags, String::new(), tool, code).await
+}
+
+/// Which tool's suggestions ?fix applies
+#[derive(Debug, Clone, Copy, poise::ChoiceParameter)]
+pub enum FixTool {
+    /// `cargo clippy --fix`, which includes rustc's suggestions
+    #[name = "clippy"]
+    Clippy,
+    /// `cargo fix`
+    #[name = "rustc"]
+    Rustc,
+}
+
+/// Reverts the `fn main` wrapping done by [`maybe_wrap`]: items stay where they are, and the
+/// statements that were moved into `fn main` are put after them
+fn strip_fn_main_wrapper(code: &str) -> String {
+    let prefix = "fn main() {\n";
+    match (code.find(prefix), code.rfind('}')) {
+        (Some(prefix_pos), Some(postfix_pos)) if prefix_pos + prefix.len() <= postfix_pos => {
+            format!(
+                "{}{}",
+                &code[..prefix_pos],
+                &code[(prefix_pos + prefix.len())..postfix_pos]
+            )
+        }
+        _ => code.to_owned(),
+    }
+}
+
+/// Glue code which sets up a cargo project for the code on the playground, runs the given shell
+/// commands in it, and prints the source file afterwards. The commands may change the source file
+fn cargo_glue_code(
+    code: &str,
+    edition: Edition,
+    crate_type: CrateType,
+    commands: &[String],
+) -> String {
+    let mut manifest = format!(
+        "[package]\nname = \"snippet\"\nversion = \"0.1.0\"\nedition = \"{}\"\n\n\
+        [dependencies]\n",
+        edition.name()
+    );
+    if let Some(header) = extract_dependencies_header(code) {
+        manifest += &header;
+        manifest += "\n";
+    }
+    let (init_flag, source_path) = match crate_type {
+        CrateType::Binary => ("--bin", "src/main.rs"),
+        CrateType::Library => ("--lib", "src/lib.rs"),
+    };
+
+    let mut generated_code = format!(
+        stringify!(
+            const CODE: &str = r#####"{}"#####;
+            const MANIFEST: &str = r#####"{}"#####;
+        ),
+        code, manifest
+    );
+    generated_code += SHELL_HELPERS;
+    generated_code += &format!(
+        r#"
+fn main() -> std::io::Result<()> {{
+    std::env::set_current_dir(cmd_stdout("mktemp -d").trim())?;
+    cmd_run("cargo init -q --vcs none --name snippet {}");
+    std::fs::write("Cargo.toml", MANIFEST)?;
+    std::fs::write("{}", CODE)?;"#,
+        init_flag, source_path
+    );
+    for command in commands {
+        // Debug formatting makes a string literal out of it
+        generated_code += &format!("\n    cmd_run({:?});", command);
+    }
+    generated_code += &format!(
+        r#"
+    print!("{{}}", std::fs::read_to_string("{}")?);
+    Ok(())
+}}"#,
+        source_path
+    );
+    generated_code
+}
+
+async fn fix_inner(
+    ctx: Context<'_>,
+    flags: CommandFlags,
+    mut flag_parse_errors: String,
+    tool: FixTool,
+    code: String,
+) -> Result<(), Error> {
+    send_stub_message(ctx).await?;
+    let code = apply_dependency_header(ctx, code, &mut flag_parse_errors).await?;
+
+    let crate_type = crate_type(&flags, &code);
+    let code = maybe_wrap_for_crate_type(&code, crate_type, ResultHandling::None);
+    let was_fn_main_wrapped = matches!(code, Cow::Owned(_));
+    let fix_command = match tool {
+        FixTool::Clippy => "cargo clippy --fix",
+        FixTool::Rustc => "cargo fix",
+    };
+    let generated_code = cargo_glue_code(
+        &code,
+        flags.edition,
+        crate_type,
+        &[format!("{} -q --offline --allow-no-vcs", fix_command)],
+    );
+
+    let request = PlaygroundRequest {
+        code: &generated_code,
+        channel: flags.channel,
+        // These flags only apply to the glue code
+        crate_type: CrateType::Binary,
+        edition: Edition::E2021,
+        mode: Mode::Debug,
+        tests: false,
+    };
+    let mut result = run_maybe_cached(
+        ctx,
+        &flags,
+        "execute",
+        &request,
+        queued(ctx, ctx.data().playground.execute(&request)),
+    )
+    .await?;
+
+    // Like in ?procmacro, the outer layer is the glue code and the inner layer is the output of
+    // the cargo invocation inside the glue code
+    result.stderr = format_play_eval_stderr(&format_play_eval_stderr(&result.stderr, false), true);
+    if !result.success {
+        result.stdout = String::new();
+        return send_reply(ctx, result, &code, &flags, &flag_parse_errors).await;
+    }
+
+    let (original, fixed) = if was_fn_main_wrapped {
+        (
+            strip_fn_main_wrapper(&code),
+            strip_fn_main_wrapper(&result.stdout),
+        )
+    } else {
+        (code.into_owned(), result.stdout)
+    };
+    if fixed.lines().eq(original.lines()) {
+        ctx.say(format!("{}Nothing to fix automatically", flag_parse_errors))
+            .await?;
+        return Ok(());
+    }
+    // The fixed code is the output, so that the overflow flag applies to it. The diff goes above
+    // it, if it leaves enough room
+    let diff = unified_diff("original.rs", &original, "fixed.rs", &fixed).await?;
+    let header = if diff.len() <= crate::MAX_MESSAGE_LENGTH / 2 {
+        format!("```diff\n{}```\n", diff)
+    } else {
+        String::from("Diff too large to show, here's the fixed code\n")
+    };
+    result.stdout = fixed;
+    result.stderr = String::new();
+
+    send_reply_with_stdout_name(
+        ctx,
+        result,
+        &code,
+        &flags,
+        &flag_parse_errors,
+        &header,
+        "fixed.rs",
+    )
+    .await
+}
+
+pub fn fix_help() -> String {
+    let mut help = generic_help(GenericHelp {
+        command: "fix",
+        desc:
+            "Apply the fixes that Clippy or rustc suggest, and show the fixed code together with \
+            a diff",
+        mode_and_channel: false,
+        warn: false,
+        run: false,
+        crate_type: true,
+        example_code: "code",
+    });
+    help += "- tool: clippy, rustc (default: clippy). Whose suggestions to apply. `clippy` \
+        includes rustc's suggestions too\n";
+    help
+}
+
+/// Migrate code to a newer Rust edition
+#[poise::command(
+    prefix_command,
+    track_edits,
+    help_text_fn = "migrate_help",
+    category = "Playground"
+)]
+pub async fn migrate(
+    ctx: Context<'_>,
+    mut flags: poise::KeyValueArgs,
+    code: Option<poise::CodeBlock>,
+) -> Result<(), Error> {
+    let code = resolve_code(ctx, code).await?;
+    let mut flag_parse_errors = String::new();
+    let from = pop_flag(&mut flags, "from", &mut flag_parse_errors);
+    let to = pop_flag(&mut flags, "to", &mut flag_parse_errors);
+    let (flags, other_flag_parse_errors) = parse_flags(flags);
+    flag_parse_errors += &other_flag_parse_errors;
+    migrate_inner(ctx, flags, flag_parse_errors, from, to, code).await
+}
+
+/// Migrate code to a newer Rust edition
+#[poise::command(slash_command)]
+pub async fn application_migrate(
+    ctx: Context<'_>,
+    #[description = "Edition the code is written in. 2018 by default"] from: Option<Edition>,
+    #[description = "Edition to migrate to. The one after `from` by default"] to: Option<Edition>,
+    #[description = "Crate type. Detected from the code by default"]
+    #[rename = "crate"]
+    crate_type: Option<CrateType>,
+    #[description = "How to send long output"] overflow: Option<OverflowStrategy>,
+) -> Result<(), Error> {
+    let code = crate::code_from_modal(ctx).await?;
+    let flags = CommandFlags {
+        crate_type,
+        overflow: overflow.unwrap_or_default(),
+        ..Default::default()
+    };
+    migrate_inner(ctx, flags, String::new(), from, to, code).await
+}
+
+/// Names of the lints in `` `-W lint-name` implied by `-W rust-2021-compatibility` `` notes, i.e.
+/// the migration lints that fired
+fn fired_migration_lints(stderr: &str) -> Vec<String> {
+    let mut lints = Vec::new();
+    for (note_end, _) in stderr.match_indices("` implied by `-W rust-") {
+        let lint = match stderr[..note_end].rfind("`-W ") {
+            Some(note_start) => stderr[(note_start + 4)..note_end].replace('-', "_"),
+            None => continue,
+        };
+        if !lints.contains(&lint) {
+            lints.push(lint);
+        }
+    }
+    lints
+}
+
+async fn migrate_inner(
+    ctx: Context<'_>,
+    flags: CommandFlags,
+    mut flag_parse_errors: String,
+    from: Option<Edition>,
+    to: Option<Edition>,
+    code: String,
+) -> Result<(), Error> {
+    let (from, to) = match (from, to) {
+        (Some(from), Some(to)) => (from, to),
+        (Some(from), None) => (from, from.next().unwrap_or(from)),
+        (None, Some(to)) => {
+            let previous = [Edition::E2015, Edition::E2018]
+                .iter()
+                .copied()
+                .find(|edition| edition.next() == Some(to));
+            (previous.unwrap_or(to), to)
+        }
+        (None, None) => (Edition::E2018, Edition::E2021),
+    };
+    if from >= to {
+        return Err(format!(
+            "Can't migrate from {} to {}. `to` must be a newer edition than `from`",
+            from.name(),
+            to.name()
+        )
+        .into());
+    }
+
+    send_stub_message(ctx).await?;
+    let code = apply_dependency_header(ctx, code, &mut flag_parse_errors).await?;
+
+    let crate_type = crate_type(&flags, &code);
+    let code = maybe_wrap_for_crate_type(&code, crate_type, ResultHandling::None);
+    let was_fn_main_wrapped = matches!(code, Cow::Owned(_));
+
+    // `cargo fix --edition` only migrates to the next edition, so bigger jumps take several steps
+    let mut commands = Vec::new();
+    let mut edition = from;
+    while let Some(next) = edition.next().filter(|&next| next <= to) {
+        // Migration lints are allowed by default. Warn about them first to find out which fire
+        commands.push(format!(
+            "RUSTFLAGS='-W rust-{}-compatibility' cargo check -q --offline",
+            next.name()
+        ));
+        commands.push("cargo fix --edition -q --offline --allow-no-vcs".to_owned());
+        commands.push(format!(
+            "sed -i 's/^edition = .*/edition = \"{}\"/' Cargo.toml",
+            next.name()
+        ));
+        edition = next;
+    }
+    let generated_code = cargo_glue_code(&code, from, crate_type, &commands);
+
+    let request = PlaygroundRequest {
+        code: &generated_code,
+        channel: flags.channel,
+        // These flags only apply to the glue code
+        crate_type: CrateType::Binary,
+        edition: Edition::E2021,
+        mode: Mode::Debug,
+        tests: false,
+    };
+    let mut result = run_maybe_cached(
+        ctx,
+        &flags,
+        "execute",
+        &request,
+        queued(ctx, ctx.data().playground.execute(&request)),
+    )
+    .await?;
+
+    if !result.success {
+        result.stdout = String::new();
+        result.stderr =
+            format_play_eval_stderr(&format_play_eval_stderr(&result.stderr, false), true);
+        return send_reply(ctx, result, &code, &flags, &flag_parse_errors).await;
+    }
+
+    let lints = fired_migration_lints(&result.stderr);
+    if lints.is_empty() && result.stdout.lines().eq(code.lines()) {
+        ctx.say(format!(
+            "{}Nothing to migrate, the code means the same in Rust {}",
+            flag_parse_errors,
+            to.name()
+        ))
+        .await?;
+        return Ok(());
+    }
+    flag_parse_errors += &format!("Migrated from Rust {} to {}", from.name(), to.name());
+    if !lints.is_empty() {
+        flag_parse_errors += &format!(
+            ". Migration lints that fired: {}",
+            lints
+                .iter()
+                .map(|lint| format!("`{}`", lint))
+                .collect::<Vec<_>>()
+                .join(", ")
+        );
+    }
+    flag_parse_errors += "\n";
+
+    // The wrapper is easiest to remove from formatted code
+    if was_fn_main_wrapped {
+        match apply_online_rustfmt(ctx, &result.stdout, to).await {
+            Ok(PlayResult {
+                success: true,
+                stdout,
+                ..
+            }) => result.stdout = stdout,
+            Ok(PlayResult {
+                success: false,
+                stderr,
+                ..
+            }) => log::warn!(
+                "Huh, rustfmt failed even though this code successfully compiled before: {}",
+                stderr
+            ),
+            Err(e) => log::warn!("Couldn't run rustfmt: {}", e),
+        }
+        result.stdout = strip_fn_main_boilerplate_from_formatted(&result.stdout);
+    }
+    // Migration warnings were already summarized above
+    result.stderr = String::new();
+
+    send_reply_with_stdout_name(
+        ctx,
+        result,
+        &code,
+        &flags,
+        &flag_parse_errors,
+        "",
+        "migrated.rs",
+    )
+    .await
+}
+
+pub fn migrate_help() -> String {
+    "Migrate code to a newer Rust edition using `cargo fix --edition`, and show the migrated code \
+together with the migration lints that fired. All code is executed on https://play.rust-lang.org.
+```rust
+?migrate from={} to={} crate={} ``\u{200B}`code``\u{200B}`
+```
+Optional arguments:
+- from: 2015, 2018 (default: 2018, or the edition before `to`)
+- to: 2018, 2021 (default: the edition after `from`)
+- crate: bin, lib (default: lib if the code consists only of items like functions and structs, \
+bin otherwise)
+- overflow: link, attachment, paginate (default: link). How to deliver output that's too long for \
+a Discord message: as a playground link, as file attachments, or split into pages
+"
+    .to_owned()
+}
@@ -0,0 +1,404 @@
+//! Playground backend which compiles and runs the code on the bot host itself
+
+use super::{api::*, backend::*};
+use crate::Error;
+
+use std::path::{Path, PathBuf};
+
+/// Compiles and runs code with the toolchains installed via rustup on the bot host. Every request
+/// gets a fresh temporary cargo project, and runs in a child process without network access, with
+/// limited time, memory and disk space, and without access to the bot's environment variables and
+/// files.
+///
+/// Requires `timeout` plus `unshare`, `mount`, `pivot_root` and `setpriv` (util-linux), and a
+/// kernel that allows unprivileged user namespaces. Miri, clippy and rustfmt requests additionally
+/// need the respective rustup components
+#[derive(Debug)]
+pub struct LocalPlayground {
+    /// Wall clock time after which the whole process tree is killed
+    timeout: std::time::Duration,
+    /// Virtual memory limit per process, in KiB
+    memory_limit_kib: u64,
+    /// Size limit for each file written, including stdout and stderr, in 512-byte blocks
+    file_size_limit_blocks: u64,
+}
+
+impl Default for LocalPlayground {
+    fn default() -> Self {
+        Self {
+            timeout: std::time::Duration::from_secs(30),
+            memory_limit_kib: 4 * 1024 * 1024,
+            // 100 MiB
+            file_size_limit_blocks: 100 * 1024 * 2,
+        }
+    }
+}
+
+/// Sets up the sandbox inside the fresh namespaces and runs the command given after the other
+/// arguments, with stdout and stderr redirected into files in the project directory.
+///
+/// The new root file system is a tmpfs which contains only read-only bind mounts of the system
+/// directories and the toolchains, a /proc of the new PID namespace, and the project directory.
+/// Everything else on the host, like the bot's `.env` and database, isn't reachable. Capabilities
+/// are dropped before running the command, so that it can't undo the mounts
+const SANDBOX_SCRIPT: &str = r#"
+set -e
+project=$1 rustup_home=$2 cargo_home=$3 memory_limit_kib=$4 file_size_limit_blocks=$5
+shift 5
+
+# New root on a tmpfs, which only contains what's mounted into it below
+root=$project/.sandbox-root
+mkdir "$root"
+mount -t tmpfs -o mode=755 none "$root"
+bind() {
+    if [ -L "$1" ]; then
+        mkdir -p "$root$(dirname "$1")"
+        cp -P "$1" "$root$1"
+    elif [ -d "$1" ]; then
+        mkdir -p "$root$1"
+        mount --bind "$1" "$root$1"
+        mount -o remount,bind,ro "$root$1"
+    elif [ -e "$1" ]; then
+        mkdir -p "$root$(dirname "$1")"
+        touch "$root$1"
+        mount --bind "$1" "$root$1"
+        mount -o remount,bind,ro "$root$1"
+    fi
+}
+for path in /usr /bin /sbin /lib /lib32 /lib64 /etc/alternatives /etc/ld.so.cache \
+    "$rustup_home" "$cargo_home" /dev/null /dev/zero /dev/random /dev/urandom; do
+    bind "$path"
+done
+mkdir -p "$root/proc" "$root/tmp"
+mount -t proc proc "$root/proc"
+mount -t tmpfs none "$root/tmp"
+# After the /tmp mount, because the project is usually in there
+mkdir -p "$root$project"
+mount --bind "$project" "$root$project"
+
+cd "$root"
+mkdir old_root
+pivot_root . old_root
+umount -l /old_root
+rmdir /old_root
+cd "$project"
+
+ulimit -v "$memory_limit_kib"
+ulimit -f "$file_size_limit_blocks"
+exec setpriv --no-new-privs --bounding-set=-all --inh-caps=-all "$@" >.stdout 2>.stderr
+"#;
+
+impl LocalPlayground {
+    /// Runs the given command in the sandbox, with the project directory as working directory.
+    /// Stdout and stderr are redirected into files, so that the file size limit applies to them
+    async fn run_sandboxed(&self, project: &Path, command: &[&str]) -> Result<PlayResult, Error> {
+        let home = std::env::var_os("HOME")
+            .map(PathBuf::from)
+            .ok_or("HOME isn't set")?;
+        let rustup_home =
+            std::env::var_os("RUSTUP_HOME").map_or_else(|| home.join(".rustup"), PathBuf::from);
+        let cargo_home =
+            std::env::var_os("CARGO_HOME").map_or_else(|| home.join(".cargo"), PathBuf::from);
+
+        let output = tokio::process::Command::new("timeout")
+            .arg("--signal=KILL")
+            .arg(self.timeout.as_secs().to_string())
+            // A new PID namespace makes sure that all processes spawned by the code die together
+            // with the sandbox, and a new network namespace has no network interfaces besides
+            // loopback. The mount namespace is where SANDBOX_SCRIPT builds the new root
+            .args(["unshare", "--user", "--map-root-user", "--mount"])
+            .args(["--net", "--pid", "--fork", "--kill-child"])
+            .args(["sh", "-c", SANDBOX_SCRIPT, "sh"])
+            .arg(project)
+            .arg(&rustup_home)
+            .arg(&cargo_home)
+            .arg(self.memory_limit_kib.to_string())
+            .arg(self.file_size_limit_blocks.to_string())
+            .args(command)
+            .current_dir(project)
+            // The bot's environment contains secrets like the Discord token
+            .env_clear()
+            .env("PATH", std::env::var_os("PATH").unwrap_or_default())
+            .env("HOME", project)
+            .env("RUSTUP_HOME", &rustup_home)
+            .env("CARGO_HOME", &cargo_home)
+            .env("CARGO_TARGET_DIR", project.join("target"))
+            .env("CARGO_TERM_COLOR", "never")
+            .stdin(std::process::Stdio::null())
+            .stdout(std::process::Stdio::null())
+            .stderr(std::process::Stdio::piped())
+            .kill_on_drop(true)
+            .output()
+            .await?;
+        let status = output.status;
+
+        let read_output = |name: &str| -> String {
+            std::fs::read(project.join(name))
+                .map(|bytes| String::from_utf8_lossy(&bytes).into_owned())
+                .unwrap_or_default()
+        };
+        let mut stderr = read_output(".stderr");
+        // Only the sandbox setup itself writes here
+        let setup_errors = String::from_utf8_lossy(&output.stderr);
+        if !setup_errors.is_empty() {
+            log::warn!("local playground sandbox setup failed: {}", setup_errors);
+            stderr += &setup_errors;
+        }
+        // With --signal=KILL, timeout kills itself too. Same message as on the playground, which
+        // `send_reply` looks for
+        if std::os::unix::process::ExitStatusExt::signal(&status) == Some(9) {
+            stderr += &format!(
+                "Killed                  timeout --signal=KILL {}\n",
+                self.timeout.as_secs()
+            );
+        }
+
+        Ok(PlayResult {
+            success: status.success(),
+            stdout: read_output(".stdout"),
+            stderr,
+        })
+    }
+
+    /// Runs a cargo subcommand in the project, with the given toolchain
+    async fn run_cargo(
+        &self,
+        project: &TempProject,
+        channel: Channel,
+        args: &[&str],
+    ) -> Result<PlayResult, Error> {
+        let toolchain = format!("+{}", channel_name(channel));
+        let mut command = vec!["cargo", toolchain.as_str()];
+        // The sandbox has no network access anyway. Must come before a potential `--`
+        command.extend(args.first());
+        command.push("--offline");
+        command.extend(args.iter().skip(1));
+
+        self.run_sandboxed(&project.path, &command).await
+    }
+}
+
+fn channel_name(channel: Channel) -> &'static str {
+    match channel {
+        Channel::Stable => "stable",
+        Channel::Beta => "beta",
+        Channel::Nightly => "nightly",
+    }
+}
+
+fn edition_name(edition: Edition) -> &'static str {
+    match edition {
+        Edition::E2015 => "2015",
+        Edition::E2018 => "2018",
+        Edition::E2021 => "2021",
+    }
+}
+
+/// Runs the `--version` command of a tool of the given toolchain. Returns None if the tool isn't
+/// installed
+async fn local_tool_version(channel: Channel, command: &[&str]) -> Option<ToolVersion> {
+    let output = tokio::process::Command::new(command[0])
+        .arg(format!("+{}", channel_name(channel)))
+        .args(&command[1..])
+        .arg("--version")
+        .output()
+        .await
+        .ok()?;
+    if !output.status.success() {
+        return None;
+    }
+
+    // For example `rustc 1.75.0 (82e1608df 2023-12-21)` or `clippy 0.1.75 (82e1608 2023-12-21)`
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let mut words = stdout.split_whitespace().skip(1);
+    Some(ToolVersion {
+        version: words.next()?.to_owned(),
+        hash: words
+            .next()
+            .unwrap_or("")
+            .trim_start_matches('(')
+            .to_owned(),
+        date: words.next().unwrap_or("").trim_end_matches(')').to_owned(),
+    })
+}
+
+async fn local_channel_versions(channel: Channel) -> ChannelVersions {
+    ChannelVersions {
+        rustc: local_tool_version(channel, &["rustc"]).await,
+        cargo: local_tool_version(channel, &["cargo"]).await,
+        rustfmt: local_tool_version(channel, &["rustfmt"]).await,
+        clippy: local_tool_version(channel, &["cargo", "clippy"]).await,
+        miri: local_tool_version(channel, &["cargo", "miri"]).await,
+    }
+}
+
+/// Temporary cargo project which is deleted when dropped
+struct TempProject {
+    path: PathBuf,
+}
+
+impl TempProject {
+    fn new(code: &str, crate_type: CrateType, edition: Edition) -> Result<Self, Error> {
+        static COUNTER: std::sync::atomic::AtomicUsize = std::sync::atomic::AtomicUsize::new(0);
+
+        let path = std::env::temp_dir().join(format!(
+            "rustbot-playground-{}-{}",
+            std::process::id(),
+            COUNTER.fetch_add(1, std::sync::atomic::Ordering::SeqCst),
+        ));
+        // Construct early, so that the directory is cleaned up if anything below fails
+        let project = Self { path };
+
+        std::fs::create_dir_all(project.path.join("src"))?;
+        std::fs::write(
+            project.path.join("Cargo.toml"),
+            format!(
+                "[package]\nname = \"playground\"\nversion = \"0.0.1\"\nedition = \"{}\"\n",
+                edition_name(edition)
+            ),
+        )?;
+        let source_file = match crate_type {
+            CrateType::Binary => "src/main.rs",
+            CrateType::Library => "src/lib.rs",
+        };
+        std::fs::write(project.path.join(source_file), code)?;
+
+        Ok(project)
+    }
+}
+
+impl Drop for TempProject {
+    fn drop(&mut self) {
+        if let Err(e) = std::fs::remove_dir_all(&self.path) {
+            log::warn!("couldn't remove {}: {}", self.path.display(), e);
+        }
+    }
+}
+
+impl PlaygroundBackend for LocalPlayground {
+    fn execute<'a>(&'a self, request: &'a PlaygroundRequest<'a>) -> BackendFuture<'a, PlayResult> {
+        let mut args = vec![match (request.tests, request.crate_type) {
+            (true, _) => "test",
+            (false, CrateType::Binary) => "run",
+            (false, CrateType::Library) => "build",
+        }];
+        if let Mode::Release = request.mode {
+            args.push("--release");
+        }
+
+        Box::pin(async move {
+            let project = TempProject::new(request.code, request.crate_type, request.edition)?;
+            self.run_cargo(&project, request.channel, &args).await
+        })
+    }
+
+    fn miri<'a>(&'a self, request: &'a MiriRequest<'a>) -> BackendFuture<'a, PlayResult> {
+        Box::pin(async move {
+            let project = TempProject::new(request.code, CrateType::Binary, request.edition)?;
+            self.run_cargo(&project, Channel::Nightly, &["miri", "run"])
+                .await
+        })
+    }
+
+    fn macro_expansion<'a>(
+        &'a self,
+        request: &'a MacroExpansionRequest<'a>,
+    ) -> BackendFuture<'a, PlayResult> {
+        Box::pin(async move {
+            let project = TempProject::new(request.code, CrateType::Binary, request.edition)?;
+            let args = ["rustc", "--", "-Zunpretty=expanded"];
+            self.run_cargo(&project, Channel::Nightly, &args).await
+        })
+    }
+
+    fn clippy<'a>(&'a self, request: &'a ClippyRequest<'a>) -> BackendFuture<'a, PlayResult> {
+        Box::pin(async move {
+            let project = TempProject::new(request.code, request.crate_type, request.edition)?;
+            // Pinned to the default channel, which is the only one ?clippy accepts
+            self.run_cargo(&project, Channel::default(), &["clippy"])
+                .await
+        })
+    }
+
+    fn format<'a>(&'a self, request: &'a FormatRequest<'a>) -> BackendFuture<'a, FormatResponse> {
+        Box::pin(async move {
+            let project = TempProject::new(request.code, CrateType::Binary, request.edition)?;
+            let result = self
+                .run_sandboxed(
+                    &project.path,
+                    &[
+                        "rustfmt",
+                        "--edition",
+                        edition_name(request.edition),
+                        "src/main.rs",
+                    ],
+                )
+                .await?;
+
+            Ok(FormatResponse {
+                success: result.success,
+                code: std::fs::read_to_string(project.path.join("src/main.rs"))?,
+                stdout: result.stdout,
+                stderr: result.stderr,
+            })
+        })
+    }
+
+    fn compile<'a>(
+        &'a self,
+        request: &'a CompileRequest<'a>,
+    ) -> BackendFuture<'a, CompileResponse> {
+        Box::pin(async move {
+            // Emitted files are written to a fixed location, so we can read them back
+            let emit = match request.target {
+                CompileTarget::Asm => "--emit=asm=output",
+                CompileTarget::LlvmIr => "--emit=llvm-ir=output",
+                CompileTarget::Mir => "--emit=mir=output",
+                CompileTarget::Hir => "-Zunpretty=hir",
+                CompileTarget::Wasm => {
+                    return Err("the local playground backend can't compile to WebAssembly".into())
+                }
+            };
+            let mut args = vec!["rustc"];
+            if let Mode::Release = request.mode {
+                args.push("--release");
+            }
+            args.extend(["--", emit]);
+
+            let project = TempProject::new(request.code, request.crate_type, request.edition)?;
+            let result = self.run_cargo(&project, request.channel, &args).await?;
+
+            // -Zunpretty prints to stdout, --emit writes into the output file
+            let code = match std::fs::read_to_string(project.path.join("output")) {
+                Ok(code) => code,
+                Err(_) => result.stdout,
+            };
+            Ok(CompileResponse {
+                success: result.success,
+                code,
+                stdout: String::new(),
+                stderr: result.stderr,
+            })
+        })
+    }
+
+    fn crates(&self) -> BackendFuture<'_, Vec<CrateInfo>> {
+        // Code is built offline with a bare manifest, so no third-party crates are available
+        Box::pin(async { Ok(Vec::new()) })
+    }
+
+    fn versions(&self) -> BackendFuture<'_, VersionsResponse> {
+        Box::pin(async {
+            Ok(VersionsResponse {
+                stable: local_channel_versions(Channel::Stable).await,
+                beta: local_channel_versions(Channel::Beta).await,
+                nightly: local_channel_versions(Channel::Nightly).await,
+            })
+        })
+    }
+
+    fn gist<'a>(&'a self, _code: &'a str, _flags: &'a CommandFlags) -> BackendFuture<'a, String> {
+        // There's no web interface to link to, so callers fall back to attaching the output
+        Box::pin(async { Err("the local playground backend can't create gists".into()) })
+    }
+}
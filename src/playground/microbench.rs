@@ -1,53 +1,537 @@
-use super::{api::*, util::*};
-use crate::{Context, Error};
+use super::{api::*, playcrates::apply_dependency_header, util::*};
+use crate::{serenity, Context, Error, OverflowStrategy};
+
+/// Prefix of the lines in which the benchmark harness reports its measurements
+const RESULT_MARKER: &str = "@microbench\t";
+/// Prefix of the lines in which the benchmark harness reports functions that it had no time for
+const SKIPPED_MARKER: &str = "@microbench-skipped\t";
 
 const BENCH_FUNCTION: &str = r#"
+#[allow(dead_code)]
 fn bench(functions: &[(&str, fn())]) {
-    const CHUNK_SIZE: usize = 1000;
+    let benchmarks = functions
+        .iter()
+        .map(|&(name, function)| (name, None, Box::new(function) as Box<dyn Fn()>))
+        .collect::<Vec<_>>();
+    measure(&benchmarks);
+}
 
-    // Warm up
-    for (_, function) in functions.iter() {
-        for _ in 0..CHUNK_SIZE {
-            (function)();
+#[allow(dead_code)]
+fn bench_sizes(functions: &[(&str, fn(usize))], sizes: &[usize]) {
+    let mut benchmarks = Vec::new();
+    for &size in sizes {
+        for &(name, function) in functions {
+            let benchmark = move || function(std::hint::black_box(size));
+            benchmarks.push((name, Some(size), Box::new(benchmark) as Box<dyn Fn()>));
         }
     }
+    measure(&benchmarks);
+}
 
-    let mut functions_chunk_times = functions.iter().map(|_| Vec::new()).collect::<Vec<_>>();
+fn measure(benchmarks: &[(&str, Option<usize>, Box<dyn Fn()>)]) {
+    use std::time::{Duration, Instant};
 
-    let start = std::time::Instant::now();
-    while (std::time::Instant::now() - start).as_secs() < 5 {
-        for (chunk_times, (_, function)) in functions_chunk_times.iter_mut().zip(functions) {
-            let start = std::time::Instant::now();
-            for _ in 0..CHUNK_SIZE {
-                (function)();
+    // Every sample runs a function often enough to take this long, so that timer resolution and
+    // loop overhead don't distort the measurement
+    const SAMPLE_DURATION: Duration = Duration::from_millis(5);
+    const TIME_BUDGET: Duration = Duration::from_secs(5);
+    const MAX_SAMPLES: usize = 1000;
+
+    fn run(function: &dyn Fn(), iterations: u64) -> Duration {
+        let start = Instant::now();
+        for _ in 0..iterations {
+            function();
+        }
+        start.elapsed()
+    }
+
+    let start = Instant::now();
+
+    // Calibrate the iterations per sample by doubling them until a sample is long enough. This
+    // also serves as warm-up, and the last calibration run is the first sample. Slow functions
+    // can use up the time budget here already, so the remaining ones are skipped then, instead of
+    // running into the playground's timeout
+    let mut iterations = Vec::new();
+    let mut samples = Vec::new();
+    for benchmark in benchmarks {
+        if start.elapsed() >= TIME_BUDGET {
+            break;
+        }
+        let mut n = 1_u64;
+        loop {
+            let elapsed = run(&*benchmark.2, n);
+            if elapsed >= SAMPLE_DURATION || n >= 1 << 40 || start.elapsed() >= TIME_BUDGET {
+                iterations.push(n);
+                samples.push(vec![elapsed.as_secs_f64() / n as f64]);
+                break;
             }
-            chunk_times.push((std::time::Instant::now() - start).as_secs_f64() / CHUNK_SIZE as f64);
+            n *= 2;
         }
     }
+    let (benchmarks, skipped) = benchmarks.split_at(iterations.len());
 
-    for (chunk_times, (function_name, _)) in functions_chunk_times.iter().zip(functions) {
-        let mean_time: f64 = chunk_times.iter().sum::<f64>() / chunk_times.len() as f64;
-        
-        let mut sum_of_squared_deviations = 0.0;
-        let mut n = 0;
-        for &time in chunk_times {
-            // Filter out outliers (there are some crazy outliers, I've checked)
-            if time < mean_time * 3.0 {
-                sum_of_squared_deviations += (time - mean_time).powi(2);
-                n += 1;
+    // The functions are run in a different random order every round, so that effects like CPU
+    // frequency scaling or cache state don't systematically favor one of them
+    let mut rng_state = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|time| time.as_nanos() as u64)
+        .unwrap_or(0)
+        | 1;
+    let mut order = (0..benchmarks.len()).collect::<Vec<_>>();
+    'sampling: while samples.iter().any(|samples| samples.len() < MAX_SAMPLES) {
+        for i in (1..order.len()).rev() {
+            rng_state ^= rng_state << 13;
+            rng_state ^= rng_state >> 7;
+            rng_state ^= rng_state << 17;
+            order.swap(i, rng_state as usize % (i + 1));
+        }
+        for &i in &order {
+            if start.elapsed() >= TIME_BUDGET {
+                break 'sampling;
             }
+            let elapsed = run(&*benchmarks[i].2, iterations[i]);
+            samples[i].push(elapsed.as_secs_f64() / iterations[i] as f64);
         }
-        let standard_deviation = f64::sqrt(sum_of_squared_deviations / n as f64);
+    }
 
+    for (&(name, size, _), samples) in benchmarks.iter().zip(&mut samples) {
+        samples.sort_by(|a, b| a.partial_cmp(b).unwrap());
+        let percentile = |p: f64| samples[((samples.len() - 1) as f64 * p).round() as usize];
+        println!(
+            "@microbench\t{}\t{}\t{:e}\t{:e}\t{:e}",
+            name,
+            size.map_or("-".to_string(), |size| size.to_string()),
+            percentile(0.5),
+            percentile(0.05),
+            percentile(0.95),
+        );
+    }
+    for &(name, size, _) in skipped {
         println!(
-            "{}: {:.1}ns ± {:.1}",
-            function_name,
-            mean_time * 1_000_000_000.0,
-            standard_deviation * 1_000_000_000.0,
+            "@microbench-skipped\t{}\t{}",
+            name,
+            size.map_or("-".to_string(), |size| size.to_string()),
         );
     }
 }"#;
 
+/// Sizes that functions with a size parameter are benchmarked with if the user didn't pass any
+const DEFAULT_SIZES: &[usize] = &[10, 100, 1000, 10000];
+/// Most sizes that a single benchmark may use, to stay within the playground time limit
+const MAX_SIZES: usize = 8;
+/// Largest size parameter, for the same reason
+const MAX_SIZE: usize = 10_000_000;
+
+/// Measurements of a single benchmarked function, in seconds per call
+struct BenchResult {
+    name: String,
+    /// Argument that the function was called with, if it takes a size parameter
+    size: Option<usize>,
+    median: f64,
+    p5: f64,
+    p95: f64,
+}
+
+/// Splits the program output into the benchmark results, the functions that were skipped for lack
+/// of time (formatted for display) and the remaining output
+fn parse_bench_output(stdout: &str) -> (Vec<BenchResult>, Vec<String>, String) {
+    let mut results = Vec::new();
+    let mut skipped = Vec::new();
+    let mut other_output = String::new();
+    for line in stdout.lines() {
+        if let Some(rest) = line.strip_prefix(SKIPPED_MARKER) {
+            let (name, size) = rest.split_once('\t').unwrap_or((rest, "-"));
+            skipped.push(match size {
+                "-" => name.to_owned(),
+                size => format!("{} (n={})", name, size),
+            });
+            continue;
+        }
+        let parse_result = |line: &str| {
+            let mut fields = line.strip_prefix(RESULT_MARKER)?.split('\t');
+            Some(BenchResult {
+                name: fields.next()?.to_owned(),
+                size: match fields.next()? {
+                    "-" => None,
+                    size => Some(size.parse().ok()?),
+                },
+                median: fields.next()?.parse().ok()?,
+                p5: fields.next()?.parse().ok()?,
+                p95: fields.next()?.parse().ok()?,
+            })
+        };
+        match parse_result(line) {
+            Some(result) => results.push(result),
+            None => {
+                other_output += line;
+                other_output += "\n";
+            }
+        }
+    }
+    (results, other_output)
+}
+
+/// Formats a duration in seconds with three significant digits and a fitting unit
+fn format_duration(seconds: f64) -> String {
+    let (value, unit) = if seconds < 1e-6 {
+        (seconds * 1e9, "ns")
+    } else if seconds < 1e-3 {
+        (seconds * 1e6, "µs")
+    } else if seconds < 1.0 {
+        (seconds * 1e3, "ms")
+    } else {
+        (seconds, "s")
+    };
+    let decimals = if value >= 100.0 {
+        0
+    } else if value >= 10.0 {
+        1
+    } else {
+        2
+    };
+    format!("{:.*} {}", decimals, value, unit)
+}
+
+/// Renders the results as a table, with each function's speed relative to the fastest one
+fn format_bench_table(results: &[BenchResult]) -> String {
+    let fastest = results
+        .iter()
+        .map(|result| result.median)
+        .fold(f64::INFINITY, f64::min);
+
+    let mut rows = vec![[
+        "Function".to_owned(),
+        "Median".to_owned(),
+        "p5 … p95".to_owned(),
+        "Relative".to_owned(),
+    ]];
+    for result in results {
+        let relative = if result.median <= fastest {
+            "1.00× (fastest)".to_owned()
+        } else {
+            format!("{:.2}× slower", result.median / fastest)
+        };
+        rows.push([
+            result.name.clone(),
+            format_duration(result.median),
+            format!(
+                "{} … {}",
+                format_duration(result.p5),
+                format_duration(result.p95)
+            ),
+            relative,
+        ]);
+    }
+
+    format_table(&rows)
+}
+
+/// Shows how the median time of each function scales with the size parameter
+fn format_scaling_table(results: &[BenchResult]) -> String {
+    let (names, sizes) = names_and_sizes(results);
+
+    let mut header = vec!["Function".to_owned()];
+    header.extend(sizes.iter().map(|size| format!("n={}", size)));
+    let mut rows = vec![header];
+    for name in &names {
+        let mut row = vec![(*name).to_owned()];
+        for &size in &sizes {
+            let result = results
+                .iter()
+                .find(|result| result.name == *name && result.size == Some(size));
+            let result = match result {
+                Some(result) => result,
+                None => {
+                    row.push("-".to_owned());
+                    continue;
+                }
+            };
+
+            let fastest_at_size = results
+                .iter()
+                .filter(|other| other.size == Some(size))
+                .all(|other| result.median <= other.median);
+            let mut cell = format_duration(result.median);
+            if fastest_at_size && names.len() > 1 {
+                cell += " *";
+            }
+            row.push(cell);
+        }
+        rows.push(row);
+    }
+
+    let mut table = format_table(&rows);
+    if names.len() > 1 {
+        table += "* fastest at this size\n";
+    }
+    table
+}
+
+/// Function names and sizes of the results, in order of first appearance
+fn names_and_sizes(results: &[BenchResult]) -> (Vec<&str>, Vec<usize>) {
+    let mut names = Vec::new();
+    let mut sizes = Vec::new();
+    for result in results {
+        if !names.contains(&result.name.as_str()) {
+            names.push(&result.name);
+        }
+        if let Some(size) = result.size {
+            if !sizes.contains(&size) {
+                sizes.push(size);
+            }
+        }
+    }
+    (names, sizes)
+}
+
+/// Lays out the rows as a left-aligned plain text table
+fn format_table<R: AsRef<[String]>>(rows: &[R]) -> String {
+    let mut column_widths = Vec::new();
+    for row in rows {
+        for (i, cell) in row.as_ref().iter().enumerate() {
+            if i == column_widths.len() {
+                column_widths.push(0);
+            }
+            column_widths[i] = usize::max(column_widths[i], cell.chars().count());
+        }
+    }
+
+    let mut table = String::new();
+    for row in rows {
+        let mut line = String::new();
+        for (cell, &width) in row.as_ref().iter().zip(&column_widths) {
+            line += &format!("{:<1$}   ", cell, width);
+        }
+        table += line.trim_end();
+        table += "\n";
+    }
+    table
+}
+
+/// Draws a horizontal bar chart of the median times, with lines spanning p5 to p95, as PNG
+fn render_bar_chart(results: &[BenchResult]) -> Result<Vec<u8>, Error> {
+    const ROW_HEIGHT: u32 = 40;
+    const MARGIN: u32 = 20;
+    const BAR_AREA_WIDTH: u32 = 600;
+    let background = image::Rgba([54, 57, 63, 255]);
+    let foreground = image::Rgba([220, 221, 222, 255]);
+    let bar_color = image::Rgba([88, 101, 242, 255]);
+    let scale = rusttype::Scale::uniform(20.0);
+    let font = &*crate::misc::FONT;
+
+    let label_width = results
+        .iter()
+        .map(|result| imageproc::drawing::text_size(scale, font, &result.name).0)
+        .max()
+        .unwrap_or(0) as u32;
+    // Leave room for the duration text to the right of the longest whisker
+    let value_text_width = 120;
+    let width = MARGIN + label_width + MARGIN + BAR_AREA_WIDTH + value_text_width + MARGIN;
+    let height = MARGIN * 2 + ROW_HEIGHT * results.len() as u32;
+
+    let max_time = results.iter().map(|result| result.p95).fold(0.0, f64::max);
+    let bar_start = (MARGIN + label_width + MARGIN) as f32;
+    let x_of = |seconds: f64| bar_start + (seconds / max_time * BAR_AREA_WIDTH as f64) as f32;
+
+    let mut image = image::RgbaImage::from_pixel(width, height, background);
+    for (i, result) in results.iter().enumerate() {
+        let row_top = MARGIN + ROW_HEIGHT * i as u32;
+        let center_y = (row_top + ROW_HEIGHT / 2) as f32;
+
+        imageproc::drawing::draw_text_mut(
+            &mut image,
+            foreground,
+            MARGIN as i32,
+            row_top as i32 + 8,
+            scale,
+            font,
+            &result.name,
+        );
+
+        let bar_width = (x_of(result.median) - bar_start).max(1.0) as u32;
+        imageproc::drawing::draw_filled_rect_mut(
+            &mut image,
+            imageproc::rect::Rect::at(bar_start as i32, row_top as i32 + 6)
+                .of_size(bar_width, ROW_HEIGHT - 12),
+            bar_color,
+        );
+
+        let (p5_x, p95_x) = (x_of(result.p5), x_of(result.p95));
+        imageproc::drawing::draw_line_segment_mut(
+            &mut image,
+            (p5_x, center_y),
+            (p95_x, center_y),
+            foreground,
+        );
+        for x in [p5_x, p95_x] {
+            imageproc::drawing::draw_line_segment_mut(
+                &mut image,
+                (x, center_y - 6.0),
+                (x, center_y + 6.0),
+                foreground,
+            );
+        }
+
+        imageproc::drawing::draw_text_mut(
+            &mut image,
+            foreground,
+            p95_x as i32 + 10,
+            row_top as i32 + 8,
+            scale,
+            font,
+            &format_duration(result.median),
+        );
+    }
+
+    let mut img_bytes = Vec::new();
+    image::DynamicImage::ImageRgba8(image).write_to(
+        &mut std::io::Cursor::new(&mut img_bytes),
+        image::ImageOutputFormat::Png,
+    )?;
+    Ok(img_bytes)
+}
+
+/// Draws the median time of each function over the size parameter as PNG, with both axes
+/// logarithmic
+fn render_scaling_chart(results: &[BenchResult]) -> Result<Vec<u8>, Error> {
+    const MARGIN: i32 = 20;
+    const PLOT_WIDTH: i32 = 600;
+    const PLOT_HEIGHT: i32 = 400;
+    const Y_AXIS_LABEL_WIDTH: i32 = 90;
+    const X_AXIS_LABEL_HEIGHT: i32 = 30;
+    let background = image::Rgba([54, 57, 63, 255]);
+    let foreground = image::Rgba([220, 221, 222, 255]);
+    let palette = [
+        image::Rgba([88, 101, 242, 255]),
+        image::Rgba([237, 66, 69, 255]),
+        image::Rgba([87, 242, 135, 255]),
+        image::Rgba([254, 231, 92, 255]),
+        image::Rgba([235, 69, 158, 255]),
+        image::Rgba([69, 221, 235, 255]),
+    ];
+    let scale = rusttype::Scale::uniform(18.0);
+    let font = &*crate::misc::FONT;
+
+    let (names, sizes) = names_and_sizes(results);
+    let legend_width = names
+        .iter()
+        .map(|name| imageproc::drawing::text_size(scale, font, name).0)
+        .max()
+        .unwrap_or(0)
+        + 30;
+    let width = MARGIN + Y_AXIS_LABEL_WIDTH + PLOT_WIDTH + MARGIN + legend_width + MARGIN;
+    let height = MARGIN + PLOT_HEIGHT + X_AXIS_LABEL_HEIGHT + MARGIN;
+    let plot_left = MARGIN + Y_AXIS_LABEL_WIDTH;
+    let plot_bottom = MARGIN + PLOT_HEIGHT;
+
+    // Maps a value onto 0..=1 on a logarithmic axis spanning the given values
+    fn log_axis(values: impl Iterator<Item = f64>) -> impl Fn(f64) -> f32 {
+        let (mut min, mut max) = (f64::INFINITY, f64::NEG_INFINITY);
+        for value in values {
+            min = min.min(value.log10());
+            max = max.max(value.log10());
+        }
+        // Don't divide by zero if there's only a single distinct value
+        if max - min < 1e-9 {
+            min -= 0.5;
+            max += 0.5;
+        }
+        move |value| ((value.log10() - min) / (max - min)) as f32
+    }
+    let x_axis = log_axis(sizes.iter().map(|&size| size.max(1) as f64));
+    let y_axis = log_axis(results.iter().map(|result| result.median));
+    let point = |size: usize, seconds: f64| {
+        (
+            plot_left as f32 + x_axis(size.max(1) as f64) * PLOT_WIDTH as f32,
+            plot_bottom as f32 - y_axis(seconds) * PLOT_HEIGHT as f32,
+        )
+    };
+
+    let mut image = image::RgbaImage::from_pixel(width as u32, height as u32, background);
+
+    // Axes, with the sizes and the fastest and slowest time as tick labels
+    imageproc::drawing::draw_line_segment_mut(
+        &mut image,
+        (plot_left as f32, MARGIN as f32),
+        (plot_left as f32, plot_bottom as f32),
+        foreground,
+    );
+    imageproc::drawing::draw_line_segment_mut(
+        &mut image,
+        (plot_left as f32, plot_bottom as f32),
+        ((plot_left + PLOT_WIDTH) as f32, plot_bottom as f32),
+        foreground,
+    );
+    for &size in &sizes {
+        let (x, _) = point(size, 1.0);
+        let label = size.to_string();
+        let label_width = imageproc::drawing::text_size(scale, font, &label).0;
+        imageproc::drawing::draw_text_mut(
+            &mut image,
+            foreground,
+            x as i32 - label_width / 2,
+            plot_bottom + 8,
+            scale,
+            font,
+            &label,
+        );
+    }
+    let medians = results.iter().map(|result| result.median);
+    let fastest = medians.clone().fold(f64::INFINITY, f64::min);
+    let slowest = medians.fold(0.0, f64::max);
+    for seconds in [fastest, slowest] {
+        let (_, y) = point(1, seconds);
+        imageproc::drawing::draw_text_mut(
+            &mut image,
+            foreground,
+            MARGIN,
+            y as i32 - 9,
+            scale,
+            font,
+            &format_duration(seconds),
+        );
+    }
+
+    for (i, name) in names.iter().enumerate() {
+        let color = palette[i % palette.len()];
+        let points = results
+            .iter()
+            .filter(|result| result.name == *name)
+            .filter_map(|result| Some(point(result.size?, result.median)))
+            .collect::<Vec<_>>();
+        for pair in points.windows(2) {
+            imageproc::drawing::draw_line_segment_mut(&mut image, pair[0], pair[1], color);
+        }
+        for &(x, y) in &points {
+            imageproc::drawing::draw_filled_circle_mut(&mut image, (x as i32, y as i32), 4, color);
+        }
+
+        let legend_x = plot_left + PLOT_WIDTH + MARGIN;
+        let legend_y = MARGIN + 25 * i as i32;
+        imageproc::drawing::draw_filled_rect_mut(
+            &mut image,
+            imageproc::rect::Rect::at(legend_x, legend_y + 4).of_size(16, 12),
+            color,
+        );
+        imageproc::drawing::draw_text_mut(
+            &mut image,
+            foreground,
+            legend_x + 24,
+            legend_y,
+            scale,
+            font,
+            name,
+        );
+    }
+
+    let mut img_bytes = Vec::new();
+    image::DynamicImage::ImageRgba8(image).write_to(
+        &mut std::io::Cursor::new(&mut img_bytes),
+        image::ImageOutputFormat::Png,
+    )?;
+    Ok(img_bytes)
+}
+
 /// Benchmark small snippets of code
 #[poise::command(
     prefix_command,
@@ -57,84 +541,201 @@ fn bench(functions: &[(&str, fn())]) {
 )]
 pub async fn microbench(
     ctx: Context<'_>,
-    flags: poise::KeyValueArgs,
-    code: poise::CodeBlock,
+    mut flags: poise::KeyValueArgs,
+    code: Option<poise::CodeBlock>,
 ) -> Result<(), Error> {
-    ctx.say(stub_message(ctx)).await?;
+    let code = resolve_code(ctx, code).await?;
+    let mut flag_parse_errors = String::new();
+    let chart = pop_flag(&mut flags, "chart", &mut flag_parse_errors).unwrap_or(false);
+    let sizes = pop_flag_list(&mut flags, "sizes", &mut flag_parse_errors);
+    let (flags, other_flag_parse_errors) = parse_flags(flags);
+    flag_parse_errors += &other_flag_parse_errors;
+    microbench_inner(ctx, flags, flag_parse_errors, chart, sizes, code).await
+}
 
-    let user_code = &code.code;
+/// Benchmark small snippets of code
+#[poise::command(slash_command)]
+pub async fn application_microbench(
+    ctx: Context<'_>,
+    #[description = "Release channel"] channel: Option<Channel>,
+    #[description = "Rust edition"] edition: Option<Edition>,
+    #[description = "Show compiler warnings"] warn: Option<bool>,
+    #[description = "Comma-separated sizes to pass to functions with a size parameter"]
+    sizes: Option<String>,
+    #[description = "Render a chart of the results"] chart: Option<bool>,
+    #[description = "How to send long output"] overflow: Option<OverflowStrategy>,
+) -> Result<(), Error> {
+    let code = crate::code_from_modal(ctx).await?;
+    let flags = CommandFlags {
+        channel: channel.unwrap_or_default(),
+        edition: edition.unwrap_or_default(),
+        warn: warn.unwrap_or_default(),
+        overflow: overflow.unwrap_or_default(),
+        ..Default::default()
+    };
+    let mut flag_parse_errors = String::new();
+    let sizes = match sizes {
+        Some(sizes) => parse_flag_list("sizes", &sizes, &mut flag_parse_errors),
+        None => Vec::new(),
+    };
+    let chart = chart.unwrap_or(false);
+    microbench_inner(ctx, flags, flag_parse_errors, chart, sizes, code).await
+}
+
+async fn microbench_inner(
+    ctx: Context<'_>,
+    flags: CommandFlags,
+    mut flag_parse_errors: String,
+    chart: bool,
+    mut sizes: Vec<usize>,
+    code: String,
+) -> Result<(), Error> {
+    send_stub_message(ctx).await?;
+    let code = apply_dependency_header(ctx, code, &mut flag_parse_errors).await?;
+
+    let user_code = &code;
     let black_box_hint = !user_code.contains("black_box");
 
     // insert convenience import for users
     let after_crate_attrs = "#[allow(unused_imports)] use std::hint::black_box;\n";
 
-    let pub_fn_indices = user_code.match_indices("pub fn ").collect::<Vec<_>>();
-    match pub_fn_indices.len() {
-        0 => {
-            ctx.say("No public functions (`pub fn`) found for benchmarking :thinking:")
-                .await?;
-            return Ok(());
-        }
-        1 => {
-            ctx.say("Please include multiple functions. Times are not comparable across runs")
-                .await?;
-            return Ok(());
-        }
-        _ => {}
-    };
-
-    // insert this after user code
-    let mut after_code = BENCH_FUNCTION.to_owned();
-    after_code += "fn main() {\nbench(&[";
-    for (index, _) in pub_fn_indices {
+    // Find the functions to benchmark, and whether they take a size parameter
+    let mut functions = Vec::new();
+    for (index, _) in user_code.match_indices("pub fn ") {
         let function_name_start = index + "pub fn ".len();
         let function_name_end = match user_code[function_name_start..].find('(') {
             Some(x) => x + function_name_start,
             None => continue,
         };
         let function_name = user_code[function_name_start..function_name_end].trim();
+        let takes_size = !user_code[function_name_end + 1..]
+            .trim_start()
+            .starts_with(')');
+        functions.push((function_name, takes_size));
+    }
+
+    let sized_function_count = functions.iter().filter(|(_, sized)| *sized).count();
+    if functions.is_empty() {
+        ctx.say("No public functions (`pub fn`) found for benchmarking :thinking:")
+            .await?;
+        return Ok(());
+    }
+    if sized_function_count != 0 && sized_function_count != functions.len() {
+        ctx.say(
+            "Either all or none of the functions must take a size parameter \
+            (`pub fn name(n: usize)`)",
+        )
+        .await?;
+        return Ok(());
+    }
+    let with_sizes = sized_function_count != 0;
+    if !with_sizes && functions.len() == 1 {
+        ctx.say("Please include multiple functions. Times are not comparable across runs")
+            .await?;
+        return Ok(());
+    }
+
+    if !with_sizes && !sizes.is_empty() {
+        flag_parse_errors += "`sizes` only applies to functions that take a size parameter\n";
+    }
+    if sizes.is_empty() {
+        sizes = DEFAULT_SIZES.to_vec();
+    }
+    sizes.sort_unstable();
+    sizes.dedup();
+    if sizes.len() > MAX_SIZES {
+        ctx.say(format!("Please pass at most {} sizes", MAX_SIZES))
+            .await?;
+        return Ok(());
+    }
+    if sizes.last().map_or(false, |&size| size > MAX_SIZE) {
+        ctx.say(format!("Sizes may be at most {}", MAX_SIZE))
+            .await?;
+        return Ok(());
+    }
 
+    // insert this after user code
+    let mut after_code = BENCH_FUNCTION.to_owned();
+    after_code += if with_sizes {
+        "fn main() {\nbench_sizes(&["
+    } else {
+        "fn main() {\nbench(&["
+    };
+    for (function_name, _) in &functions {
         after_code += &format!("(\"{0}\", {0}), ", function_name);
     }
-    after_code += "]);\n}\n";
+    if with_sizes {
+        after_code += &format!("], &{:?});\n}}\n", sizes);
+    } else {
+        after_code += "]);\n}\n";
+    }
 
     // final assembled code
     let code = hoise_crate_attributes(user_code, after_crate_attrs, &after_code);
 
-    let (flags, mut flag_parse_errors) = parse_flags(flags);
-    let mut result: PlayResult = ctx
-        .data()
-        .http
-        .post("https://play.rust-lang.org/execute")
-        .json(&PlaygroundRequest {
-            code: &code,
-            channel: flags.channel,
-            crate_type: CrateType::Binary,
-            edition: flags.edition,
-            mode: Mode::Release, // benchmarks on debug don't make sense
-            tests: false,
-        })
-        .send()
-        .await?
-        .json()
-        .await?;
+    let request = PlaygroundRequest {
+        code: &code,
+        channel: flags.channel,
+        crate_type: CrateType::Binary,
+        edition: flags.edition,
+        mode: Mode::Release, // benchmarks on debug don't make sense
+        tests: false,
+    };
+    let mut result = run_maybe_cached(
+        ctx,
+        &flags,
+        "execute",
+        &request,
+        queued(ctx, ctx.data().playground.execute(&request)),
+    )
+    .await?;
 
     result.stderr = format_play_eval_stderr(&result.stderr, flags.warn);
 
+    let (bench_results, skipped, other_output) = parse_bench_output(&result.stdout);
+    if !bench_results.is_empty() {
+        let table = if with_sizes {
+            format_scaling_table(&bench_results)
+        } else {
+            format_bench_table(&bench_results)
+        };
+        result.stdout = other_output + &table;
+        if !skipped.is_empty() {
+            result.stdout += &format!("Skipped because the time ran out: {}\n", skipped.join(", "));
+        }
+    }
+
     if black_box_hint {
         flag_parse_errors +=
             "Hint: use the black_box function to prevent computations from being optimized out\n";
     }
-    send_reply(ctx, result, &code, &flags, &flag_parse_errors).await
+    send_reply(ctx, result, &code, &flags, &flag_parse_errors).await?;
+
+    if chart && !bench_results.is_empty() {
+        let chart = if with_sizes {
+            render_scaling_chart(&bench_results)?
+        } else {
+            render_bar_chart(&bench_results)?
+        };
+        ctx.send(
+            poise::CreateReply::new()
+                .attachment(serenity::CreateAttachment::bytes(chart, "microbench.png")),
+        )
+        .await?;
+    }
+
+    Ok(())
 }
 
 pub fn microbench_help() -> String {
-    generic_help(GenericHelp {
+    let mut help = generic_help(GenericHelp {
         command: "microbench",
         desc: "\
-Benchmarks small snippets of code by running them repeatedly. Public functions \
-are run in blocks of 1000 repetitions in a cycle until 5 seconds have \
-passed. Measurements are averaged and standard deviation is calculated for each
+Benchmarks small snippets of code by running them repeatedly. The number of repetitions per \
+sample is calibrated for each public function, then samples of all functions are taken in a \
+randomly shuffled order every round until 5 seconds have passed. If calibrating slow functions \
+uses up the 5 seconds, the remaining functions are skipped. The results table shows the median, \
+5th and 95th percentile time per call of each function and how it compares to the fastest
 
 Use the `std::hint::black_box` function, which is already imported, to wrap results of \
 computations that shouldn't be optimized out. Also wrap computation inputs in `black_box(...)` \
@@ -143,6 +744,7 @@ that should be opaque to the optimizer: `number * 2` produces optimized integer
         mode_and_channel: false,
         warn: true,
         run: false,
+        crate_type: false,
         example_code: "
 pub fn add() {
     black_box(black_box(42.0) + black_box(99.0));
@@ -151,5 +753,19 @@ pub fn mul() {
     black_box(black_box(42.0) * black_box(99.0));
 }
 ",
-    })
+    });
+    help += "- chart: true, false (default: false). Also render the results as a chart\n";
+    help += &format!(
+        "- sizes: comma-separated list of up to {} numbers of at most {} (default: {}). If the \
+        functions take a size parameter, like `pub fn lookup(n: usize)`, each one is benchmarked \
+        with every size, to show how it scales\n",
+        MAX_SIZES,
+        MAX_SIZE,
+        DEFAULT_SIZES
+            .iter()
+            .map(|size| size.to_string())
+            .collect::<Vec<_>>()
+            .join(","),
+    );
+    help
 }
@@ -1,5 +1,6 @@
-use super::{api::*, util::*};
-use crate::{Context, Error};
+use super::{api::*, diagnostics, playcrates::apply_dependency_header, util::*};
+use crate::{Context, Error, OverflowStrategy};
+use poise::serenity_prelude as serenity;
 
 use std::borrow::Cow;
 
@@ -13,32 +14,69 @@ use std::borrow::Cow;
 pub async fn miri(
     ctx: Context<'_>,
     flags: poise::KeyValueArgs,
-    code: poise::CodeBlock,
+    code: Option<poise::CodeBlock>,
 ) -> Result<(), Error> {
-    ctx.say(stub_message(ctx)).await?;
-
-    let code = &maybe_wrap(&code.code, ResultHandling::Discard);
+    let code = resolve_code(ctx, code).await?;
     let (flags, flag_parse_errors) = parse_flags(flags);
+    miri_inner(ctx, flags, flag_parse_errors, code).await
+}
 
-    let mut result: PlayResult = ctx
-        .data()
-        .http
-        .post("https://play.rust-lang.org/miri")
-        .json(&MiriRequest {
-            code,
-            edition: flags.edition,
-        })
-        .send()
-        .await?
-        .json()
-        .await?;
+/// Run code and detect undefined behavior using Miri
+#[poise::command(slash_command)]
+pub async fn application_miri(
+    ctx: Context<'_>,
+    #[description = "Rust edition"] edition: Option<Edition>,
+    #[description = "How to send long output"] overflow: Option<OverflowStrategy>,
+) -> Result<(), Error> {
+    let code = crate::code_from_modal(ctx).await?;
+    let flags = CommandFlags {
+        edition: edition.unwrap_or_default(),
+        overflow: overflow.unwrap_or_default(),
+        ..Default::default()
+    };
+    miri_inner(ctx, flags, String::new(), code).await
+}
+
+async fn miri_inner(
+    ctx: Context<'_>,
+    mut flags: CommandFlags,
+    mut flag_parse_errors: String,
+    code: String,
+) -> Result<(), Error> {
+    // Miri only exists on nightly. Recorded in the flags for the version footer
+    flags.channel = Channel::Nightly;
+    send_stub_message(ctx).await?;
+    let code = apply_dependency_header(ctx, code, &mut flag_parse_errors).await?;
+
+    let code = &maybe_wrap(&code, ResultHandling::Discard);
 
-    result.stderr = extract_relevant_lines(
-        &result.stderr,
-        &["Running `/playground"],
-        &["error: aborting"],
+    let request = MiriRequest {
+        code,
+        edition: flags.edition,
+    };
+    let mut result = run_maybe_cached(
+        ctx,
+        &flags,
+        "miri",
+        &request,
+        queued(ctx, ctx.data().playground.miri(&request)),
     )
-    .to_owned();
+    .await?;
+
+    // Miri's own diagnostics are printed while "running" the program, i.e. after the compiler
+    // output, intermixed with the program's stderr
+    let output = diagnostics::parse_cargo_output(&result.stderr);
+    let (miri_diagnostics, program_stderr) = diagnostics::parse_diagnostics(&output.program_stderr);
+    result.stderr = [
+        diagnostics::render_diagnostics(&output.diagnostics),
+        program_stderr,
+        diagnostics::render_diagnostics(&miri_diagnostics),
+    ]
+    .iter()
+    .filter(|section| !section.is_empty())
+    .map(|section| section.as_str())
+    .collect::<Vec<_>>()
+    .join("\n");
 
     send_reply(ctx, result, code, &flags, &flag_parse_errors).await
 }
@@ -53,6 +91,7 @@ pub fn miri_help() -> String {
         // warnings out
         warn: false,
         run: false,
+        crate_type: false,
         example_code: "code",
     })
 }
@@ -67,33 +106,58 @@ pub fn miri_help() -> String {
 pub async fn expand(
     ctx: Context<'_>,
     flags: poise::KeyValueArgs,
-    code: poise::CodeBlock,
+    code: Option<poise::CodeBlock>,
+) -> Result<(), Error> {
+    let code = resolve_code(ctx, code).await?;
+    let (flags, flag_parse_errors) = parse_flags(flags);
+    expand_inner(ctx, flags, flag_parse_errors, code).await
+}
+
+/// Expand macros to their raw desugared form
+#[poise::command(slash_command)]
+pub async fn application_expand(
+    ctx: Context<'_>,
+    #[description = "Rust edition"] edition: Option<Edition>,
+    #[description = "How to send long output"] overflow: Option<OverflowStrategy>,
+) -> Result<(), Error> {
+    let code = crate::code_from_modal(ctx).await?;
+    let flags = CommandFlags {
+        edition: edition.unwrap_or_default(),
+        overflow: overflow.unwrap_or_default(),
+        ..Default::default()
+    };
+    expand_inner(ctx, flags, String::new(), code).await
+}
+
+async fn expand_inner(
+    ctx: Context<'_>,
+    mut flags: CommandFlags,
+    mut flag_parse_errors: String,
+    code: String,
 ) -> Result<(), Error> {
-    ctx.say(stub_message(ctx)).await?;
+    // Macro expansion needs nightly. Recorded in the flags for the version footer
+    flags.channel = Channel::Nightly;
+    send_stub_message(ctx).await?;
+    let code = apply_dependency_header(ctx, code, &mut flag_parse_errors).await?;
 
-    let code = maybe_wrap(&code.code, ResultHandling::None);
+    let code = maybe_wrap(&code, ResultHandling::None);
     let was_fn_main_wrapped = matches!(code, Cow::Owned(_));
-    let (flags, flag_parse_errors) = parse_flags(flags);
 
-    let mut result: PlayResult = ctx
+    let request = MacroExpansionRequest {
+        code: &code,
+        edition: flags.edition,
+    };
+    let mut result = ctx
         .data()
-        .http
-        .post("https://play.rust-lang.org/macro-expansion")
-        .json(&MacroExpansionRequest {
-            code: &code,
-            edition: flags.edition,
-        })
-        .send()
-        .await?
-        .json()
+        .result_cache
+        .get_or_insert_with(
+            "macro-expansion",
+            &request,
+            queued(ctx, ctx.data().playground.macro_expansion(&request)),
+        )
         .await?;
 
-    result.stderr = extract_relevant_lines(
-        &result.stderr,
-        &["Finished ", "Compiling playground"],
-        &["error: aborting"],
-    )
-    .to_owned();
+    result.stderr = format_compiler_stderr(&result.stderr);
 
     if result.success {
         match apply_online_rustfmt(ctx, &result.stdout, flags.edition).await {
@@ -103,10 +167,21 @@ pub async fn expand(
         }
     }
     if was_fn_main_wrapped {
+        // Remove the standard library prelude injection, which comes before the user's items
+        strip_prelude_injection(&mut result.stdout);
         result.stdout = strip_fn_main_boilerplate_from_formatted(&result.stdout);
     }
 
-    send_reply(ctx, result, &code, &flags, &flag_parse_errors).await
+    send_reply_with_stdout_name(
+        ctx,
+        result,
+        &code,
+        &flags,
+        &flag_parse_errors,
+        "",
+        "expanded.rs",
+    )
+    .await
 }
 
 pub fn expand_help() -> String {
@@ -116,6 +191,7 @@ pub fn expand_help() -> String {
         mode_and_channel: false,
         warn: false,
         run: false,
+        crate_type: false,
         example_code: "code",
     })
 }
@@ -129,57 +205,202 @@ pub fn expand_help() -> String {
 )]
 pub async fn clippy(
     ctx: Context<'_>,
-    flags: poise::KeyValueArgs,
-    code: poise::CodeBlock,
+    mut flags: poise::KeyValueArgs,
+    code: Option<poise::CodeBlock>,
 ) -> Result<(), Error> {
-    ctx.say(stub_message(ctx)).await?;
+    let code = resolve_code(ctx, code).await?;
+    let mut flag_parse_errors = String::new();
+    let options = ClippyOptions {
+        groups: pop_flag_list(&mut flags, "groups", &mut flag_parse_errors),
+        allow: pop_flag_list(&mut flags, "allow", &mut flag_parse_errors),
+        deny: pop_flag_list(&mut flags, "deny", &mut flag_parse_errors),
+    };
+    let (flags, other_flag_parse_errors) = parse_flags(flags);
+    flag_parse_errors += &other_flag_parse_errors;
+    clippy_inner(ctx, flags, flag_parse_errors, options, code).await
+}
 
-    let code = &format!(
-        // dead_code: https://github.com/kangalioo/rustbot/issues/44
-        // let_unit_value: silence warning about `let _ = { ... }` wrapper that swallows return val
-        "#![allow(dead_code, clippy::let_unit_value)] {}",
-        maybe_wrap(&code.code, ResultHandling::Discard)
-    );
-    let (flags, flag_parse_errors) = parse_flags(flags);
+/// Catch common mistakes using the Clippy linter
+#[poise::command(slash_command)]
+pub async fn application_clippy(
+    ctx: Context<'_>,
+    #[description = "Rust edition"] edition: Option<Edition>,
+    #[description = "Crate type. Detected from the code by default"]
+    #[rename = "crate"]
+    crate_type: Option<CrateType>,
+    #[description = "Comma-separated extra lint groups: pedantic, nursery, restriction, cargo"]
+    groups: Option<String>,
+    #[description = "Comma-separated lints to allow, like needless_return"] allow: Option<String>,
+    #[description = "Comma-separated lints to deny, like rustc::unsafe_code"] deny: Option<String>,
+    #[description = "How to send long output"] overflow: Option<OverflowStrategy>,
+) -> Result<(), Error> {
+    let code = crate::code_from_modal(ctx).await?;
+    let flags = CommandFlags {
+        edition: edition.unwrap_or_default(),
+        crate_type,
+        overflow: overflow.unwrap_or_default(),
+        ..Default::default()
+    };
+    let mut flag_parse_errors = String::new();
+    let options = ClippyOptions {
+        groups: match groups {
+            Some(groups) => parse_flag_list("groups", &groups, &mut flag_parse_errors),
+            None => Vec::new(),
+        },
+        allow: match allow {
+            Some(allow) => parse_flag_list("allow", &allow, &mut flag_parse_errors),
+            None => Vec::new(),
+        },
+        deny: match deny {
+            Some(deny) => parse_flag_list("deny", &deny, &mut flag_parse_errors),
+            None => Vec::new(),
+        },
+    };
+    clippy_inner(ctx, flags, flag_parse_errors, options, code).await
+}
 
-    let mut result: PlayResult = ctx
-        .data()
-        .http
-        .post("https://play.rust-lang.org/clippy")
-        .json(&ClippyRequest {
-            code,
-            edition: flags.edition,
-            crate_type: CrateType::Binary,
+/// Clippy lint groups which aren't enabled by default
+#[derive(Debug, Clone, Copy, poise::ChoiceParameter)]
+enum LintGroup {
+    #[name = "pedantic"]
+    Pedantic,
+    #[name = "nursery"]
+    Nursery,
+    #[name = "restriction"]
+    Restriction,
+    #[name = "cargo"]
+    Cargo,
+}
+
+/// Flags that only ?clippy understands
+struct ClippyOptions {
+    /// Lint groups to warn about in addition to the default ones
+    groups: Vec<LintGroup>,
+    /// Lint names, as accepted by [`lint_path`]
+    allow: Vec<String>,
+    /// Lint names, as accepted by [`lint_path`]
+    deny: Vec<String>,
+}
+
+/// Turns a lint name into the path used in lint attributes. Bare names like `needless_return` or
+/// `needless-return` are Clippy lints. rustc's lints are written with a `rustc::` prefix, like
+/// `rustc::unused_variables`, and other paths like `clippy::needless_return` or
+/// `rustdoc::broken_intra_doc_links` stay as they are. None if it's not a valid lint name
+fn lint_path(name: &str) -> Option<String> {
+    let name = name.to_lowercase().replace('-', "_");
+    let is_valid = |path: &str| {
+        path.split("::").all(|segment| {
+            !segment.is_empty()
+                && segment
+                    .chars()
+                    .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '_')
         })
-        .send()
-        .await?
-        .json()
-        .await?;
+    };
+    if !is_valid(&name) {
+        return None;
+    }
+
+    Some(if let Some(name) = name.strip_prefix("rustc::") {
+        name.to_owned()
+    } else if name.contains("::") {
+        name
+    } else {
+        format!("clippy::{}", name)
+    })
+}
+
+/// Crate-level attributes that configure the lints. They're all put in a single line so that the
+/// line numbers in Clippy's output stay the same
+fn lint_attributes(options: &ClippyOptions, flag_parse_errors: &mut String) -> String {
+    // dead_code: https://github.com/kangalioo/rustbot/issues/44
+    // let_unit_value: silence warning about `let _ = { ... }` wrapper that swallows return val
+    let mut attributes = String::from("#![allow(dead_code, clippy::let_unit_value)]");
+
+    let groups = options
+        .groups
+        .iter()
+        .map(|group| format!("clippy::{}", group.name()))
+        .collect::<Vec<_>>();
+    if !groups.is_empty() {
+        attributes += &format!(" #![warn({})]", groups.join(", "));
+    }
+
+    // After the groups, so that single lints of a group can be allowed or denied
+    for (level, lints) in [("allow", &options.allow), ("deny", &options.deny)] {
+        let mut lint_paths = Vec::new();
+        for lint in lints {
+            match lint_path(lint) {
+                Some(lint_path) => lint_paths.push(lint_path),
+                None => *flag_parse_errors += &format!("invalid `{}` value `{}`\n", level, lint),
+            }
+        }
+        if !lint_paths.is_empty() {
+            attributes += &format!(" #![{}({})]", level, lint_paths.join(", "));
+        }
+    }
+
+    attributes
+}
+
+async fn clippy_inner(
+    ctx: Context<'_>,
+    flags: CommandFlags,
+    mut flag_parse_errors: String,
+    options: ClippyOptions,
+    code: String,
+) -> Result<(), Error> {
+    // Clippy requests don't carry a channel, so running it on any other channel isn't possible
+    if !matches!(flags.channel, Channel::Nightly) {
+        return Err("Clippy can only run on the nightly channel".into());
+    }
 
-    result.stderr = extract_relevant_lines(
-        &result.stderr,
-        &["Checking playground", "Running `/playground"],
-        &[
-            "error: aborting",
-            "1 warning emitted",
-            "warnings emitted",
-            "Finished ",
-        ],
+    send_stub_message(ctx).await?;
+    let code = apply_dependency_header(ctx, code, &mut flag_parse_errors).await?;
+
+    let crate_type = crate_type(&flags, &code);
+    let code = &format!(
+        "{} {}",
+        lint_attributes(&options, &mut flag_parse_errors),
+        maybe_wrap_for_crate_type(&code, crate_type, ResultHandling::Discard)
+    );
+
+    let request = ClippyRequest {
+        code,
+        edition: flags.edition,
+        crate_type,
+    };
+    let mut result = run_maybe_cached(
+        ctx,
+        &flags,
+        "clippy",
+        &request,
+        queued(ctx, ctx.data().playground.clippy(&request)),
     )
-    .to_owned();
+    .await?;
+
+    result.stderr = format_compiler_stderr(&result.stderr);
 
     send_reply(ctx, result, code, &flags, &flag_parse_errors).await
 }
 
 pub fn clippy_help() -> String {
-    generic_help(GenericHelp {
+    let mut help = generic_help(GenericHelp {
         command: "clippy",
         desc: "Catch common mistakes and improve the code using the Clippy linter",
         mode_and_channel: false,
         warn: false,
         run: false,
+        crate_type: true,
         example_code: "code",
-    })
+    });
+    help += "- groups: comma-separated lint groups to enable in addition to the default ones: \
+        pedantic, nursery, restriction, cargo\n";
+    help += "- allow: comma-separated lints to allow, like needless_return or \
+        rustc::unused_variables\n";
+    help += "- deny: comma-separated lints to deny, like unwrap_used or rustc::unsafe_code\n";
+    help += "Lint names without a path are Clippy lints. rustc's lints need a `rustc::` prefix. \
+        Names that aren't valid lint names are reported and ignored\n";
+    help
 }
 
 /// Format code using rustfmt
@@ -191,31 +412,299 @@ pub fn clippy_help() -> String {
 )]
 pub async fn fmt(
     ctx: Context<'_>,
-    flags: poise::KeyValueArgs,
-    code: poise::CodeBlock,
+    mut flags: poise::KeyValueArgs,
+    code: Option<poise::CodeBlock>,
 ) -> Result<(), Error> {
-    ctx.say(stub_message(ctx)).await?;
+    let code = resolve_code(ctx, code).await?;
+    let mut flag_parse_errors = String::new();
+    let options = RustfmtOptions {
+        max_width: pop_flag(&mut flags, "max_width", &mut flag_parse_errors),
+        tab_spaces: pop_flag(&mut flags, "tab_spaces", &mut flag_parse_errors),
+        use_small_heuristics: pop_flag(&mut flags, "use_small_heuristics", &mut flag_parse_errors),
+        imports_granularity: pop_flag(&mut flags, "imports_granularity", &mut flag_parse_errors),
+        group_imports: pop_flag(&mut flags, "group_imports", &mut flag_parse_errors),
+        diff: pop_flag(&mut flags, "diff", &mut flag_parse_errors).unwrap_or(false),
+    };
+    let (flags, other_flag_parse_errors) = parse_flags(flags);
+    flag_parse_errors += &other_flag_parse_errors;
+    fmt_inner(ctx, flags, flag_parse_errors, options, code).await
+}
 
-    let code = &maybe_wrap(&code.code, ResultHandling::None);
-    let was_fn_main_wrapped = matches!(code, Cow::Owned(_));
-    let (flags, flag_parse_errors) = parse_flags(flags);
+/// Format code using rustfmt
+#[poise::command(slash_command)]
+pub async fn application_fmt(
+    ctx: Context<'_>,
+    #[description = "Rust edition"] edition: Option<Edition>,
+    #[description = "Maximum line width"] max_width: Option<usize>,
+    #[description = "Number of spaces per indentation level"] tab_spaces: Option<usize>,
+    #[description = "How eagerly to put things on a single line"] use_small_heuristics: Option<
+        SmallHeuristics,
+    >,
+    #[description = "How to group imports into use statements (nightly only)"]
+    imports_granularity: Option<ImportsGranularity>,
+    #[description = "How to order use statements into groups (nightly only)"] group_imports: Option<
+        GroupImports,
+    >,
+    #[description = "Show a diff against the input instead of the whole formatted code"]
+    diff: Option<bool>,
+    #[description = "How to send long output"] overflow: Option<OverflowStrategy>,
+) -> Result<(), Error> {
+    let code = crate::code_from_modal(ctx).await?;
+    let flags = CommandFlags {
+        edition: edition.unwrap_or_default(),
+        overflow: overflow.unwrap_or_default(),
+        ..Default::default()
+    };
+    let options = RustfmtOptions {
+        max_width,
+        tab_spaces,
+        use_small_heuristics,
+        imports_granularity,
+        group_imports,
+        diff: diff.unwrap_or(false),
+    };
+    fmt_inner(ctx, flags, String::new(), options, code).await
+}
+
+/// Format code using rustfmt, as found in the first Rust code block of a message
+#[poise::command(
+    context_menu_command = "Format with rustfmt",
+    ephemeral,
+    category = "Playground"
+)]
+pub async fn format_with_rustfmt(ctx: Context<'_>, msg: serenity::Message) -> Result<(), Error> {
+    let code = code_from_message(&msg)
+        .await?
+        .ok_or("The message doesn't contain any Rust code")?;
+    let options = RustfmtOptions::default();
+    fmt_inner(ctx, CommandFlags::default(), String::new(), options, code).await
+}
+
+#[derive(Debug, Clone, Copy, poise::ChoiceParameter)]
+pub enum SmallHeuristics {
+    #[name = "Default"]
+    Default,
+    #[name = "Off"]
+    Off,
+    #[name = "Max"]
+    Max,
+}
+
+#[derive(Debug, Clone, Copy, poise::ChoiceParameter)]
+pub enum ImportsGranularity {
+    #[name = "Preserve"]
+    Preserve,
+    #[name = "Crate"]
+    Crate,
+    #[name = "Module"]
+    Module,
+    #[name = "Item"]
+    Item,
+    #[name = "One"]
+    One,
+}
+
+#[derive(Debug, Clone, Copy, poise::ChoiceParameter)]
+pub enum GroupImports {
+    #[name = "Preserve"]
+    Preserve,
+    #[name = "StdExternalCrate"]
+    StdExternalCrate,
+    #[name = "One"]
+    One,
+}
 
-    let mut result = apply_online_rustfmt(ctx, code, flags.edition).await?;
+/// Flags that only ?fmt understands. Each option is a rustfmt.toml setting, None means rustfmt's
+/// default
+#[derive(Default)]
+struct RustfmtOptions {
+    max_width: Option<usize>,
+    tab_spaces: Option<usize>,
+    use_small_heuristics: Option<SmallHeuristics>,
+    /// Nightly only
+    imports_granularity: Option<ImportsGranularity>,
+    /// Nightly only
+    group_imports: Option<GroupImports>,
+    /// Whether to show a diff against the input instead of the formatted code
+    diff: bool,
+}
+
+impl RustfmtOptions {
+    /// Contents of a rustfmt.toml with the given options. Empty if there are none
+    fn config_file(&self) -> String {
+        let mut config = String::new();
+        if let Some(max_width) = self.max_width {
+            config += &format!("max_width = {}\n", max_width);
+        }
+        if let Some(tab_spaces) = self.tab_spaces {
+            config += &format!("tab_spaces = {}\n", tab_spaces);
+        }
+        if let Some(use_small_heuristics) = self.use_small_heuristics {
+            config += &format!(
+                "use_small_heuristics = \"{}\"\n",
+                use_small_heuristics.name()
+            );
+        }
+        if let Some(imports_granularity) = self.imports_granularity {
+            config += &format!("imports_granularity = \"{}\"\n", imports_granularity.name());
+        }
+        if let Some(group_imports) = self.group_imports {
+            config += &format!("group_imports = \"{}\"\n", group_imports.name());
+        }
+        config
+    }
+
+    fn needs_nightly(&self) -> bool {
+        self.imports_granularity.is_some() || self.group_imports.is_some()
+    }
+}
+
+/// Runs rustfmt with a config file on the playground, because the playground's format endpoint
+/// doesn't take any options
+async fn run_configured_rustfmt(
+    ctx: Context<'_>,
+    code: &str,
+    flags: &mut CommandFlags,
+    options: &RustfmtOptions,
+    flag_parse_errors: &mut String,
+) -> Result<PlayResult, Error> {
+    if options.needs_nightly() && !matches!(flags.channel, Channel::Nightly) {
+        *flag_parse_errors +=
+            "`imports_granularity` and `group_imports` need nightly, so nightly is used\n";
+        flags.channel = Channel::Nightly;
+    }
+
+    let mut generated_code = format!(
+        stringify!(
+            const CODE: &str = r#####"{}"#####;
+            const CONFIG: &str = r#####"{}"#####;
+        ),
+        code,
+        options.config_file()
+    );
+    generated_code += SHELL_HELPERS;
+    generated_code += &format!(
+        r#"
+fn main() -> std::io::Result<()> {{
+    std::env::set_current_dir(cmd_stdout("mktemp -d").trim())?;
+    std::fs::write("rustfmt.toml", CONFIG)?;
+    std::fs::write("main.rs", CODE)?;
+    cmd_run("rustfmt --edition {} main.rs");
+    print!("{{}}", std::fs::read_to_string("main.rs")?);
+    Ok(())
+}}"#,
+        flags.edition.name()
+    );
+
+    let request = PlaygroundRequest {
+        code: &generated_code,
+        channel: flags.channel,
+        // These flags only apply to the glue code
+        crate_type: CrateType::Binary,
+        edition: Edition::E2021,
+        mode: Mode::Debug,
+        tests: false,
+    };
+    let mut result = ctx
+        .data()
+        .result_cache
+        .get_or_insert_with(
+            "execute",
+            &request,
+            queued(ctx, ctx.data().playground.execute(&request)),
+        )
+        .await?;
+
+    // The outer layer is the glue code, the inner layer is rustfmt's output
+    result.stderr = format_play_eval_stderr(&result.stderr, false);
+    if !result.success {
+        result.stdout = String::new();
+    }
+    Ok(result)
+}
+
+async fn fmt_inner(
+    ctx: Context<'_>,
+    mut flags: CommandFlags,
+    mut flag_parse_errors: String,
+    options: RustfmtOptions,
+    code: String,
+) -> Result<(), Error> {
+    send_stub_message(ctx).await?;
+
+    let wrapped_code = &maybe_wrap(&code, ResultHandling::None);
+    let was_fn_main_wrapped = matches!(wrapped_code, Cow::Owned(_));
+
+    let mut result = if options.config_file().is_empty() {
+        apply_online_rustfmt(ctx, wrapped_code, flags.edition).await?
+    } else {
+        run_configured_rustfmt(
+            ctx,
+            wrapped_code,
+            &mut flags,
+            &options,
+            &mut flag_parse_errors,
+        )
+        .await?
+    };
 
     if was_fn_main_wrapped {
         result.stdout = strip_fn_main_boilerplate_from_formatted(&result.stdout);
     }
 
-    send_reply(ctx, result, code, &flags, &flag_parse_errors).await
+    if options.diff && result.success {
+        if result.stdout.lines().eq(code.lines()) {
+            ctx.say(format!(
+                "{}The code is already formatted",
+                flag_parse_errors
+            ))
+            .await?;
+            return Ok(());
+        }
+        result.stdout = unified_diff("original.rs", &code, "formatted.rs", &result.stdout).await?;
+        return send_reply_with_stdout_name(
+            ctx,
+            result,
+            wrapped_code,
+            &flags,
+            &flag_parse_errors,
+            "",
+            "formatted.diff",
+        )
+        .await;
+    }
+
+    send_reply_with_stdout_name(
+        ctx,
+        result,
+        wrapped_code,
+        &flags,
+        &flag_parse_errors,
+        "",
+        "formatted.rs",
+    )
+    .await
 }
 
 pub fn fmt_help() -> String {
-    generic_help(GenericHelp {
+    let mut help = generic_help(GenericHelp {
         command: "fmt",
         desc: "Format code using rustfmt",
         mode_and_channel: false,
         warn: false,
         run: false,
+        crate_type: false,
         example_code: "code",
-    })
+    });
+    help += "- max_width: maximum line width (default: 100)\n";
+    help += "- tab_spaces: number of spaces per indentation level (default: 4)\n";
+    help += "- use_small_heuristics: Default, Off, Max (default: Default). How eagerly to put \
+        things on a single line\n";
+    help += "- imports_granularity: Preserve, Crate, Module, Item, One (default: Preserve). How \
+        to group imports into use statements. Nightly only\n";
+    help += "- group_imports: Preserve, StdExternalCrate, One (default: Preserve). How to order \
+        use statements into groups. Nightly only\n";
+    help += "- diff: true, false (default: false). Show a diff against the input instead of the \
+        whole formatted code\n";
+    help
 }
@@ -1,13 +1,31 @@
 //! run rust code on the rust-lang playground
 
 mod api;
+mod backend;
+mod diagnostics;
+mod local;
+mod snippet;
 mod util;
 
+mod compare;
+mod compile;
+mod fix;
 mod microbench;
 mod misc_commands;
 mod play_eval;
+mod playcrates;
 mod procmacro;
+mod test;
+mod versions;
+pub use backend::{FallbackPlayground, HttpPlayground, PlaygroundBackend};
+pub use compare::*;
+pub use compile::*;
+pub use fix::*;
+pub use local::LocalPlayground;
 pub use microbench::*;
 pub use misc_commands::*;
 pub use play_eval::*;
+pub use playcrates::*;
 pub use procmacro::*;
+pub use test::*;
+pub use versions::*;
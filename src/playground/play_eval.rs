@@ -1,39 +1,42 @@
-use super::{api::*, util::*};
-use crate::{Context, Error};
+use super::{api::*, playcrates::apply_dependency_header, util::*};
+use crate::{Context, Error, OverflowStrategy};
+use poise::serenity_prelude as serenity;
 
 // play and eval work similarly, so this function abstracts over the two
 async fn play_or_eval(
     ctx: Context<'_>,
-    flags: poise::KeyValueArgs,
+    mut flags: CommandFlags,
+    mut flag_parse_errors: String,
     force_warnings: bool, // If true, force enable warnings regardless of flags
-    code: poise::CodeBlock,
+    code: String,
     result_handling: ResultHandling,
 ) -> Result<(), Error> {
-    ctx.say(stub_message(ctx)).await?;
+    send_stub_message(ctx).await?;
+    let code = apply_dependency_header(ctx, code, &mut flag_parse_errors).await?;
 
-    let code = maybe_wrap(&code.code, result_handling);
-    let (mut flags, flag_parse_errors) = parse_flags(flags);
+    let crate_type = crate_type(&flags, &code);
+    let code = maybe_wrap_for_crate_type(&code, crate_type, result_handling);
 
     if force_warnings {
         flags.warn = true;
     }
 
-    let mut result: PlayResult = ctx
-        .data()
-        .http
-        .post("https://play.rust-lang.org/execute")
-        .json(&PlaygroundRequest {
-            code: &code,
-            channel: flags.channel,
-            crate_type: CrateType::Binary,
-            edition: flags.edition,
-            mode: flags.mode,
-            tests: false,
-        })
-        .send()
-        .await?
-        .json()
-        .await?;
+    let request = PlaygroundRequest {
+        code: &code,
+        channel: flags.channel,
+        crate_type,
+        edition: flags.edition,
+        mode: flags.mode,
+        tests: false,
+    };
+    let mut result = run_maybe_cached(
+        ctx,
+        &flags,
+        "execute",
+        &request,
+        queued(ctx, ctx.data().playground.execute(&request)),
+    )
+    .await?;
 
     result.stderr = format_play_eval_stderr(&result.stderr, flags.warn);
 
@@ -50,18 +53,61 @@ async fn play_or_eval(
 pub async fn play(
     ctx: Context<'_>,
     flags: poise::KeyValueArgs,
-    code: poise::CodeBlock,
+    code: Option<poise::CodeBlock>,
+) -> Result<(), Error> {
+    let code = resolve_code(ctx, code).await?;
+    let (flags, flag_parse_errors) = parse_flags(flags);
+    play_or_eval(
+        ctx,
+        flags,
+        flag_parse_errors,
+        false,
+        code,
+        ResultHandling::None,
+    )
+    .await
+}
+
+/// Compile and run Rust code in a playground
+#[poise::command(slash_command)]
+pub async fn application_play(
+    ctx: Context<'_>,
+    #[description = "Release channel"] channel: Option<Channel>,
+    #[description = "Compilation mode"] mode: Option<Mode>,
+    #[description = "Rust edition"] edition: Option<Edition>,
+    #[description = "Show compiler warnings"] warn: Option<bool>,
+    #[description = "Crate type. Detected from the code by default"]
+    #[rename = "crate"]
+    crate_type: Option<CrateType>,
+    #[description = "How to send long output"] overflow: Option<OverflowStrategy>,
+    #[description = "Show the exact rustc version below the output"] version: Option<bool>,
+    #[description = "Reuse the result of an identical earlier run"] cache: Option<bool>,
 ) -> Result<(), Error> {
-    play_or_eval(ctx, flags, false, code, ResultHandling::None).await
+    let code = crate::code_from_modal(ctx).await?;
+    let flags = CommandFlags {
+        channel: channel.unwrap_or_default(),
+        mode: mode.unwrap_or_default(),
+        edition: edition.unwrap_or_default(),
+        warn: warn.unwrap_or_default(),
+        crate_type,
+        overflow: overflow.unwrap_or_default(),
+        version: version.unwrap_or_default(),
+        cache: cache.unwrap_or_default(),
+        ..Default::default()
+    };
+    play_or_eval(ctx, flags, String::new(), false, code, ResultHandling::None).await
 }
 
 pub fn play_help() -> String {
     generic_help(GenericHelp {
         command: "play",
-        desc: "Compile and run Rust code",
+        desc: "Compile and run Rust code. Crates listed by `?playcrates` can be declared in a \
+        cargo-script style `//! [dependencies]` header at the start of the code, to check that \
+        they're available and show which versions are used",
         mode_and_channel: true,
         warn: true,
         run: false,
+        crate_type: true,
         example_code: "code",
     })
 }
@@ -76,9 +122,19 @@ pub fn play_help() -> String {
 pub async fn playwarn(
     ctx: Context<'_>,
     flags: poise::KeyValueArgs,
-    code: poise::CodeBlock,
+    code: Option<poise::CodeBlock>,
 ) -> Result<(), Error> {
-    play_or_eval(ctx, flags, true, code, ResultHandling::None).await
+    let code = resolve_code(ctx, code).await?;
+    let (flags, flag_parse_errors) = parse_flags(flags);
+    play_or_eval(
+        ctx,
+        flags,
+        flag_parse_errors,
+        true,
+        code,
+        ResultHandling::None,
+    )
+    .await
 }
 
 pub fn playwarn_help() -> String {
@@ -88,6 +144,7 @@ pub fn playwarn_help() -> String {
         mode_and_channel: true,
         warn: false,
         run: false,
+        crate_type: true,
         example_code: "code",
     })
 }
@@ -102,9 +159,57 @@ pub fn playwarn_help() -> String {
 pub async fn eval(
     ctx: Context<'_>,
     flags: poise::KeyValueArgs,
-    code: poise::CodeBlock,
+    code: Option<poise::CodeBlock>,
 ) -> Result<(), Error> {
-    play_or_eval(ctx, flags, false, code, ResultHandling::Print).await
+    let code = resolve_code(ctx, code).await?;
+    let (flags, flag_parse_errors) = parse_flags(flags);
+    play_or_eval(
+        ctx,
+        flags,
+        flag_parse_errors,
+        false,
+        code,
+        ResultHandling::Print,
+    )
+    .await
+}
+
+/// Evaluate a single Rust expression
+#[poise::command(slash_command)]
+pub async fn application_eval(
+    ctx: Context<'_>,
+    #[description = "Release channel"] channel: Option<Channel>,
+    #[description = "Compilation mode"] mode: Option<Mode>,
+    #[description = "Rust edition"] edition: Option<Edition>,
+    #[description = "Show compiler warnings"] warn: Option<bool>,
+    #[description = "Crate type. Detected from the code by default"]
+    #[rename = "crate"]
+    crate_type: Option<CrateType>,
+    #[description = "How to send long output"] overflow: Option<OverflowStrategy>,
+    #[description = "Show the exact rustc version below the output"] version: Option<bool>,
+    #[description = "Reuse the result of an identical earlier run"] cache: Option<bool>,
+) -> Result<(), Error> {
+    let code = crate::code_from_modal(ctx).await?;
+    let flags = CommandFlags {
+        channel: channel.unwrap_or_default(),
+        mode: mode.unwrap_or_default(),
+        edition: edition.unwrap_or_default(),
+        warn: warn.unwrap_or_default(),
+        crate_type,
+        overflow: overflow.unwrap_or_default(),
+        version: version.unwrap_or_default(),
+        cache: cache.unwrap_or_default(),
+        ..Default::default()
+    };
+    play_or_eval(
+        ctx,
+        flags,
+        String::new(),
+        false,
+        code,
+        ResultHandling::Print,
+    )
+    .await
 }
 
 pub fn eval_help() -> String {
@@ -114,6 +219,21 @@ pub fn eval_help() -> String {
         mode_and_channel: true,
         warn: true,
         run: false,
+        crate_type: true,
         example_code: "code",
     })
 }
+
+/// Run the first Rust code block of a message on the playground
+#[poise::command(
+    context_menu_command = "Run on Playground",
+    ephemeral,
+    category = "Playground"
+)]
+pub async fn run_on_playground(ctx: Context<'_>, msg: serenity::Message) -> Result<(), Error> {
+    let code = code_from_message(&msg)
+        .await?
+        .ok_or("The message doesn't contain any Rust code")?;
+    let flags = CommandFlags::default();
+    play_or_eval(ctx, flags, String::new(), false, code, ResultHandling::None).await
+}
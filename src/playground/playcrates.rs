@@ -0,0 +1,304 @@
+//! Third-party crates on the playground, and cargo-script style dependency headers in snippets
+
+use super::{api::*, util::*};
+use crate::{Context, Data, Error};
+
+/// Cached list of the crates that the playground provides
+#[derive(Default, Debug)]
+pub struct PlaygroundCrates {
+    crates: Vec<CrateInfo>,
+    last_update_time: Option<std::time::Instant>,
+}
+
+/// How long the crate list is cached before it's fetched again
+const CRATE_LIST_UPDATE_PERIOD: std::time::Duration = std::time::Duration::from_secs(60 * 60 * 12);
+
+async fn fetch_playground_crates(data: &Data) -> Result<Vec<CrateInfo>, Error> {
+    {
+        let cache = data.playground_crates.lock().unwrap();
+        if let Some(last_update_time) = cache.last_update_time {
+            if last_update_time.elapsed() < CRATE_LIST_UPDATE_PERIOD {
+                return Ok(cache.crates.clone());
+            }
+        }
+    }
+
+    let crates = data.playground.crates().await?;
+    log::info!("updating playground crate list: {} crates", crates.len());
+    *data.playground_crates.lock().unwrap() = PlaygroundCrates {
+        crates: crates.clone(),
+        last_update_time: Some(std::time::Instant::now()),
+    };
+    Ok(crates)
+}
+
+/// Crate names are compared like cargo does, i.e. `-` and `_` are interchangeable
+fn normalize_crate_name(name: &str) -> String {
+    name.to_lowercase().replace('-', "_")
+}
+
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b = b.chars().collect::<Vec<_>>();
+    let mut previous_row = (0..=b.len()).collect::<Vec<_>>();
+    for (i, a_char) in a.chars().enumerate() {
+        let mut row = vec![i + 1];
+        for (j, &b_char) in b.iter().enumerate() {
+            let substitution_cost = if a_char == b_char { 0 } else { 1 };
+            row.push(
+                (previous_row[j] + substitution_cost)
+                    .min(previous_row[j + 1] + 1)
+                    .min(row[j] + 1),
+            );
+        }
+        previous_row = row;
+    }
+    previous_row[b.len()]
+}
+
+/// Finds the available crate whose name is closest to the given one, if any is close enough
+fn suggest_crate<'a>(name: &str, crates: &'a [CrateInfo]) -> Option<&'a str> {
+    let name = normalize_crate_name(name);
+    crates
+        .iter()
+        .map(|crate_| {
+            let distance = edit_distance(&name, &normalize_crate_name(&crate_.name));
+            (distance, crate_.name.as_str())
+        })
+        .filter(|&(distance, _)| distance <= usize::max(2, name.len() / 3))
+        .min_by_key(|&(distance, _)| distance)
+        .map(|(_, crate_name)| crate_name)
+}
+
+/// Checks whether a version satisfies a Cargo version requirement like `0.8`, `^1.2.3`, `~1.2` or
+/// `=1.0.0`. Requirements that this doesn't understand, like ranges, are assumed to match
+fn version_matches(requirement: &str, version: &str) -> bool {
+    fn parse(version: &str) -> Option<Vec<u64>> {
+        version
+            .trim()
+            .split('.')
+            .map(|part| part.parse().ok())
+            .collect()
+    }
+
+    let (operator, required) = match requirement.find(|c: char| c.is_ascii_digit()) {
+        Some(i) => requirement.split_at(i),
+        None => return true,
+    };
+    let required = match parse(required) {
+        Some(required) if (1..=3).contains(&required.len()) => required,
+        _ => return true,
+    };
+    // Ignore pre-release and build metadata
+    let version = match parse(version.split(&['-', '+'][..]).next().unwrap_or("")) {
+        Some(version) if version.len() == 3 => version,
+        _ => return true,
+    };
+
+    let mut padded_required = required.clone();
+    padded_required.resize(3, 0);
+    let at_least_required = version >= padded_required;
+    let matches_prefix = |len: usize| version[..len] == required[..len];
+
+    match operator.trim() {
+        // Caret requirements allow changes that keep the leftmost non-zero component
+        "" | "^" => {
+            let significant = required
+                .iter()
+                .position(|&part| part != 0)
+                .unwrap_or(required.len() - 1);
+            at_least_required && matches_prefix(significant + 1)
+        }
+        "~" => at_least_required && matches_prefix(required.len().min(2)),
+        "=" => matches_prefix(required.len()),
+        ">=" => at_least_required,
+        _ => true,
+    }
+}
+
+/// A dependency declared in a dependency header
+struct Dependency {
+    name: String,
+    /// `*` if the dependency doesn't specify a version
+    version_requirement: String,
+}
+
+/// Parses a line of a `[dependencies]` table, like `rand = "0.8"` or
+/// `serde = { version = "1", features = ["derive"] }`
+fn parse_dependency(line: &str) -> Option<Dependency> {
+    let (name, value) = line.split_once('=')?;
+    let value = value.trim();
+
+    let version_requirement = match value.strip_prefix('{') {
+        Some(table) => table.split(',').find_map(|entry| {
+            let (key, value) = entry.split_once('=')?;
+            if key.trim() != "version" {
+                return None;
+            }
+            Some(value.trim().trim_end_matches('}').trim().trim_matches('"'))
+        }),
+        None => Some(value.trim_matches('"')),
+    };
+
+    Some(Dependency {
+        name: name.trim().trim_matches('"').to_owned(),
+        version_requirement: version_requirement.unwrap_or("*").to_owned(),
+    })
+}
+
+/// Looks for a cargo-script style dependency header in the comments at the start of the code:
+///
+/// ```rust,ignore
+/// //! ```cargo
+/// //! [dependencies]
+/// //! rand = "0.8"
+/// //! ```
+/// ```
+///
+/// The fence lines are optional. Returns the dependencies, and the code with the `//!` comments
+/// turned into regular comments, because inner doc comments aren't allowed once the code is
+/// wrapped in `fn main`. Line numbers stay the same that way
+fn split_dependency_header(code: &str) -> Option<(Vec<Dependency>, String)> {
+    let is_comment = |line: &str| line.trim_start().starts_with("//");
+    let header_len = code
+        .split_inclusive('\n')
+        .take_while(|line| is_comment(line) || line.trim().is_empty())
+        .map(str::len)
+        .sum::<usize>();
+    let (header, rest) = code.split_at(header_len);
+
+    let dependencies = extract_dependencies_header(header)?
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .filter_map(parse_dependency)
+        .collect();
+
+    let header = header
+        .split_inclusive('\n')
+        .map(|line| match line.trim_start().strip_prefix("//!") {
+            Some(comment) => format!("//{}", comment),
+            None => line.to_owned(),
+        })
+        .collect::<String>();
+    Some((dependencies, header + rest))
+}
+
+/// Handles a dependency header at the start of the code, see [`split_dependency_header`].
+///
+/// The playground can't install crates, so the dependencies are only checked against the crates
+/// it provides. Unavailable crates are rejected. The versions that will actually be used are
+/// appended to `notes`, so that results are reproducible
+pub async fn apply_dependency_header(
+    ctx: Context<'_>,
+    code: String,
+    notes: &mut String,
+) -> Result<String, Error> {
+    let (dependencies, code) = match split_dependency_header(&code) {
+        Some(x) => x,
+        None => return Ok(code),
+    };
+
+    let available_crates = match fetch_playground_crates(ctx.data()).await {
+        Ok(crates) if !crates.is_empty() => crates,
+        Ok(_) => {
+            *notes += "Warning: this playground doesn't provide any third-party crates\n";
+            return Ok(code);
+        }
+        Err(e) => {
+            log::warn!("couldn't fetch playground crate list: {}", e);
+            *notes += "Warning: couldn't check the dependencies against the playground's crates\n";
+            return Ok(code);
+        }
+    };
+
+    let mut unavailable = Vec::new();
+    let mut pinned = Vec::new();
+    for dependency in &dependencies {
+        let name = normalize_crate_name(&dependency.name);
+        let crate_ = available_crates
+            .iter()
+            .find(|crate_| normalize_crate_name(&crate_.name) == name);
+        let crate_ = match crate_ {
+            Some(crate_) => crate_,
+            None => {
+                unavailable.push(match suggest_crate(&dependency.name, &available_crates) {
+                    Some(suggestion) => {
+                        format!("`{}` (did you mean `{}`?)", dependency.name, suggestion)
+                    }
+                    None => format!("`{}`", dependency.name),
+                });
+                continue;
+            }
+        };
+
+        if !version_matches(&dependency.version_requirement, &crate_.version) {
+            *notes += &format!(
+                "Warning: `{}` version `{}` was requested, but the playground has `{}`\n",
+                crate_.name, dependency.version_requirement, crate_.version
+            );
+        }
+        pinned.push(format!("`{} = \"={}\"`", crate_.name, crate_.version));
+    }
+
+    if !unavailable.is_empty() {
+        return Err(format!(
+            "The playground doesn't provide {}. Use `?playcrates` to search the available crates",
+            unavailable.join(", ")
+        )
+        .into());
+    }
+    if !pinned.is_empty() {
+        *notes += &format!("Dependencies: {}\n", pinned.join(", "));
+    }
+    Ok(code)
+}
+
+/// List and search the third-party crates available on the playground
+#[poise::command(
+    prefix_command,
+    slash_command,
+    broadcast_typing,
+    track_edits,
+    category = "Playground"
+)]
+pub async fn playcrates(
+    ctx: Context<'_>,
+    #[description = "Only show crates whose name contains this"] query: Option<String>,
+) -> Result<(), Error> {
+    let crates = fetch_playground_crates(ctx.data()).await?;
+    let query = query.as_deref().map(str::trim).unwrap_or("");
+
+    let normalized_query = normalize_crate_name(query);
+    let mut matching = crates
+        .iter()
+        .filter(|crate_| normalize_crate_name(&crate_.name).contains(&normalized_query))
+        .collect::<Vec<_>>();
+    matching.sort_by(|a, b| a.name.cmp(&b.name));
+
+    if matching.is_empty() {
+        let mut response = format!("No playground crate matches `{}`", query);
+        if let Some(suggestion) = suggest_crate(query, &crates) {
+            response += &format!(". Did you mean `{}`?", suggestion);
+        }
+        ctx.say(response).await?;
+        return Ok(());
+    }
+
+    let text_start = if query.is_empty() {
+        format!(
+            "{} crates are available on the playground:\n```\n",
+            matching.len()
+        )
+    } else {
+        format!(
+            "{} of the playground's crates match `{}`:\n```\n",
+            matching.len(),
+            query
+        )
+    };
+    let text_body = matching
+        .iter()
+        .map(|crate_| format!("{} {}\n", crate_.name, crate_.version))
+        .collect::<String>();
+    crate::paginate::paginate_text(ctx, &text_start, &text_body, "```").await
+}
@@ -1,5 +1,6 @@
 use super::{api::*, util::*};
-use crate::{Context, Error};
+use crate::{Context, Error, OverflowStrategy};
+use std::borrow::Cow;
 
 /// Compile and use a procedural macro
 #[poise::command(
@@ -10,98 +11,252 @@ use crate::{Context, Error};
 )]
 pub async fn procmacro(
     ctx: Context<'_>,
-    flags: poise::KeyValueArgs,
+    mut flags: poise::KeyValueArgs,
     macro_code: poise::CodeBlock,
     usage_code: poise::CodeBlock,
 ) -> Result<(), Error> {
-    ctx.say(stub_message(ctx)).await?;
+    let mut flag_parse_errors = String::new();
+    let options = ProcMacroOptions {
+        deps: pop_flag_list(&mut flags, "deps", &mut flag_parse_errors),
+        expand: pop_flag(&mut flags, "expand", &mut flag_parse_errors).unwrap_or(false),
+    };
+    let (flags, other_flag_parse_errors) = parse_flags(flags);
+    flag_parse_errors += &other_flag_parse_errors;
+    procmacro_inner(
+        ctx,
+        flags,
+        flag_parse_errors,
+        options,
+        macro_code.code,
+        usage_code.code,
+    )
+    .await
+}
+
+/// Modal which asks for both code snippets, because slash command options can't contain
+/// multi-line text
+#[derive(Debug, poise::Modal)]
+#[name = "Enter code"]
+struct ProcMacroModal {
+    #[name = "Proc macro code"]
+    #[placeholder = "#[proc_macro]\npub fn foo(input: proc_macro::TokenStream) -> proc_macro::TokenStream"]
+    #[paragraph]
+    macro_code: String,
+    #[name = "Usage code (crate name: procmacro)"]
+    #[placeholder = "procmacro::foo!();"]
+    #[paragraph]
+    usage_code: String,
+}
+
+/// Compile and use a procedural macro
+#[poise::command(slash_command)]
+pub async fn application_procmacro(
+    ctx: Context<'_>,
+    #[description = "Show compiler warnings"] warn: Option<bool>,
+    #[description = "Run the usage code, instead of only compiling it"] run: Option<bool>,
+    #[description = "Comma-separated dependencies of the proc macro crate, like syn,quote"]
+    deps: Option<String>,
+    #[description = "Show the usage code with all macros expanded"] expand: Option<bool>,
+    #[description = "How to send long output"] overflow: Option<OverflowStrategy>,
+) -> Result<(), Error> {
+    let app_ctx = match ctx {
+        Context::Application(ctx) => ctx,
+        Context::Prefix(_) => return Err("code modals only work in slash commands".into()),
+    };
+    let modal: ProcMacroModal = poise::Modal::execute(app_ctx)
+        .await?
+        .ok_or("You didn't submit any code in time")?;
+    let flags = CommandFlags {
+        warn: warn.unwrap_or_default(),
+        run: run.unwrap_or_default(),
+        overflow: overflow.unwrap_or_default(),
+        ..Default::default()
+    };
+    let mut flag_parse_errors = String::new();
+    let options = ProcMacroOptions {
+        deps: match deps {
+            Some(deps) => parse_flag_list("deps", &deps, &mut flag_parse_errors),
+            None => Vec::new(),
+        },
+        expand: expand.unwrap_or(false),
+    };
+    procmacro_inner(
+        ctx,
+        flags,
+        flag_parse_errors,
+        options,
+        modal.macro_code,
+        modal.usage_code,
+    )
+    .await
+}
 
-    let macro_code = macro_code.code;
-    let usage_code = maybe_wrap(&usage_code.code, ResultHandling::None);
+/// Flags that only ?procmacro understands
+struct ProcMacroOptions {
+    /// Crate names to add to the proc macro crate's dependencies, with any version
+    deps: Vec<String>,
+    /// Whether to show the macro expansion of the usage code
+    expand: bool,
+}
+
+/// Assembles the Cargo.toml of the proc macro crate. Dependencies come from the `deps` flag and
+/// from a `// [dependencies]` header comment in the macro code. If a crate is in both, the header
+/// wins, since it may specify a version or features
+fn procmacro_manifest(macro_code: &str, deps: &[String], flag_parse_errors: &mut String) -> String {
+    let header = extract_dependencies_header(macro_code).unwrap_or_default();
+    // Crate names are the same with dashes and underscores
+    let normalize = |name: &str| name.trim().trim_matches('"').replace('-', "_");
+    let mut dep_names = header
+        .lines()
+        .filter_map(|line| line.split(&['=', '.'][..]).next())
+        .map(normalize)
+        .collect::<Vec<_>>();
 
-    let (flags, flag_parse_errors) = parse_flags(flags);
+    let mut manifest = String::from(
+        "[package]\nname = \"procmacro\"\nversion = \"0.1.0\"\nedition = \"2021\"\n\n\
+        [lib]\nproc-macro = true\n\n[dependencies]\n",
+    );
+    for dep in deps {
+        if dep.is_empty()
+            || !dep
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+        {
+            *flag_parse_errors += &format!("invalid `deps` value `{}`\n", dep);
+            continue;
+        }
+        // Cargo rejects manifests with duplicate keys
+        if dep_names.contains(&normalize(dep)) {
+            continue;
+        }
+        dep_names.push(normalize(dep));
+        // The playground has no network access, so whichever version it has cached is used
+        manifest += &format!("{} = \"*\"\n", dep);
+    }
+    if !header.is_empty() {
+        manifest += &header;
+        manifest += "\n";
+    }
+    manifest
+}
+
+async fn procmacro_inner(
+    ctx: Context<'_>,
+    mut flags: CommandFlags,
+    mut flag_parse_errors: String,
+    options: ProcMacroOptions,
+    macro_code: String,
+    usage_code: String,
+) -> Result<(), Error> {
+    send_stub_message(ctx).await?;
+
+    if options.expand && flags.run {
+        // Program output would be mixed into the expanded code otherwise
+        flag_parse_errors += "`run` is ignored with `expand=true`\n";
+        flags.run = false;
+    }
+
+    let usage_code = maybe_wrap(&usage_code, ResultHandling::None);
+    let was_fn_main_wrapped = matches!(usage_code, Cow::Owned(_));
+    let manifest = procmacro_manifest(&macro_code, &options.deps, &mut flag_parse_errors);
 
     let mut generated_code = format!(
         stringify!(
             const MACRO_CODE: &str = r#####"{}"#####;
             const USAGE_CODE: &str = r#####"{}"#####;
+            const MANIFEST: &str = r#####"{}"#####;
         ),
-        macro_code, usage_code
+        macro_code, usage_code, manifest
     );
+    generated_code += SHELL_HELPERS;
     generated_code += r#"
-pub fn cmd_run(cmd: &str) {
-    let status = std::process::Command::new("/bin/sh")
-        .args(&["-c", cmd])
-        .status()
-        .unwrap();
-    if !status.success() {
-        std::process::exit(-1);
-    }
-}
-
-pub fn cmd_stdout(cmd: &str) -> String {
-    let output = std::process::Command::new("/bin/sh")
-        .args(&["-c", cmd])
-        .output()
-        .unwrap();
-    String::from_utf8(output.stdout).unwrap()
-}
-
 fn main() -> std::io::Result<()> {
-    use std::io::Write as _;
     std::env::set_current_dir(cmd_stdout("mktemp -d").trim())?;
     cmd_run("cargo init -q --name procmacro --lib");
     std::fs::write("src/lib.rs", MACRO_CODE)?;
     std::fs::write("src/main.rs", USAGE_CODE)?;
-    std::fs::OpenOptions::new()
-        .write(true)
-        .append(true)
-        .open("Cargo.toml")?
-        .write_all(b"[lib]\nproc-macro = true")?;
+    std::fs::write("Cargo.toml", MANIFEST)?;
     cmd_run("cargo"#;
     generated_code += if flags.run { " r" } else { " c" };
-    generated_code += r#" -q --bin procmacro");
+    generated_code += r#" -q --offline --bin procmacro");"#;
+    if options.expand {
+        // Warnings were already shown by the previous build
+        generated_code += r#"
+    cmd_run("cargo rustc -q --offline --bin procmacro -- -Zunpretty=expanded 2>/dev/null");"#;
+    }
+    generated_code += r#"
     Ok(())
 }"#;
 
-    let mut result: PlayResult = ctx
-        .data()
-        .http
-        .post("https://play.rust-lang.org/execute")
-        .json(&PlaygroundRequest {
-            code: &generated_code,
-            channel: Channel::Nightly, // so that inner proc macro gets nightly too
-            // These flags only apply to the glue code
-            crate_type: CrateType::Binary,
-            edition: Edition::E2021,
-            mode: Mode::Debug,
-            tests: false,
-        })
-        .send()
-        .await?
-        .json()
-        .await?;
+    // So that the inner proc macro gets nightly too. Recorded in the flags for the version footer
+    flags.channel = Channel::Nightly;
+    let request = PlaygroundRequest {
+        code: &generated_code,
+        channel: flags.channel,
+        // These flags only apply to the glue code
+        crate_type: CrateType::Binary,
+        edition: Edition::E2021,
+        mode: Mode::Debug,
+        tests: false,
+    };
+    let mut result = run_maybe_cached(
+        ctx,
+        &flags,
+        "execute",
+        &request,
+        queued(ctx, ctx.data().playground.execute(&request)),
+    )
+    .await?;
 
-    // funky
-    result.stderr = format_play_eval_stderr(
-        &format_play_eval_stderr(&result.stderr, flags.warn),
-        flags.warn,
-    );
+    // funky: the outer layer is the glue code, whose warnings are irrelevant, and the inner layer
+    // is the output of the cargo invocation inside the glue code
+    result.stderr =
+        format_play_eval_stderr(&format_play_eval_stderr(&result.stderr, false), flags.warn);
+
+    if !options.expand {
+        return send_reply(ctx, result, &generated_code, &flags, &flag_parse_errors).await;
+    }
 
-    send_reply(ctx, result, &generated_code, &flags, &flag_parse_errors).await
+    if result.success {
+        match apply_online_rustfmt(ctx, &result.stdout, Edition::E2021).await {
+            Ok(PlayResult { success: true, stdout, .. }) => result.stdout = stdout,
+            Ok(PlayResult { success: false, stderr, .. }) => log::warn!("Huh, rustfmt failed even though this code successfully passed through macro expansion before: {}", stderr),
+            Err(e) => log::warn!("Couldn't run rustfmt: {}", e),
+        }
+    }
+    if was_fn_main_wrapped {
+        strip_prelude_injection(&mut result.stdout);
+        result.stdout = strip_fn_main_boilerplate_from_formatted(&result.stdout);
+    }
+    send_reply_with_stdout_name(
+        ctx,
+        result,
+        &generated_code,
+        &flags,
+        &flag_parse_errors,
+        "",
+        "expanded.rs",
+    )
+    .await
 }
 
 pub fn procmacro_help() -> String {
-    generic_help(GenericHelp {
+    let mut help = generic_help(GenericHelp {
         command: "procmacro",
         desc: "\
 Compiles a procedural macro by providing two snippets: one for the \
 proc-macro code, and one for the usage code which can refer to the proc-macro crate as \
 `procmacro`. By default, the code is only compiled, _not run_! To run the final code too, pass
-`run=true`.",
+`run=true`.
+
+Dependencies of the proc macro crate, like `syn` and `quote`, can be passed as `deps=syn,quote` or \
+listed in a `// [dependencies]` header comment at the start of the proc macro code, followed by \
+one `// name = \"version\"` comment line per dependency. Only crates available on the playground \
+can be used",
         mode_and_channel: false,
         warn: true,
         run: true,
+        crate_type: false,
         example_code: "
 #[proc_macro]
 pub fn foo(_: proc_macro::TokenStream) -> proc_macro::TokenStream {
@@ -110,5 +265,10 @@ pub fn foo(_: proc_macro::TokenStream) -> proc_macro::TokenStream {
 ``\u{200B}` ``\u{200B}`
 procmacro::foo!();
 ",
-    })
+    });
+    help += "- deps: comma-separated crate names, like syn,quote. Dependencies of the proc macro \
+        crate\n";
+    help +=
+        "- expand: true, false (default: false). Show the usage code with all macros expanded\n";
+    help
 }
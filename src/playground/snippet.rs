@@ -0,0 +1,523 @@
+//! Splits code snippets into crate attributes, items and statements, so that each part can be put
+//! where it belongs when wrapping the snippet in a `fn main`
+//!
+//! This is not a full Rust parser. It only tokenizes the code (so that comments, strings and char
+//! literals don't confuse it) and looks at the first few tokens of each top-level chunk to decide
+//! what it is. That's enough for the kind of code people post in Discord.
+
+use std::ops::Range;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Token<'a> {
+    /// Identifier or keyword
+    Ident(&'a str),
+    /// Lifetime or loop label, like `'a`
+    Lifetime,
+    /// String, char or number literal
+    Literal,
+    /// `//!` or `/*!` comment
+    InnerDocComment,
+    Punct(char),
+}
+
+/// Number of bytes of a possibly nested block comment at the start of `s`
+fn block_comment_len(s: &str) -> usize {
+    let bytes = s.as_bytes();
+    let mut depth = 0;
+    let mut i = 0;
+    while i < bytes.len() {
+        if bytes[i..].starts_with(b"/*") {
+            depth += 1;
+            i += 2;
+        } else if bytes[i..].starts_with(b"*/") {
+            depth -= 1;
+            i += 2;
+            if depth == 0 {
+                return i;
+            }
+        } else {
+            i += 1;
+        }
+    }
+    s.len()
+}
+
+/// Number of bytes of the string literal starting at the quote at the start of `s`
+fn quoted_len(s: &str) -> usize {
+    // Only looking for ASCII characters, so iterating bytes is fine even with multibyte chars
+    let bytes = s.as_bytes();
+    let mut i = 1;
+    while i < bytes.len() {
+        match bytes[i] {
+            b'\\' => i += 2,
+            b'"' => return i + 1,
+            _ => i += 1,
+        }
+    }
+    s.len()
+}
+
+/// Number of bytes of the raw string literal (`r"..."`, `br#"..."#` etc.) at the start of `s`, if
+/// there is one
+fn raw_string_len(s: &str) -> Option<usize> {
+    let after_prefix = s
+        .strip_prefix('b')
+        .or_else(|| s.strip_prefix('c'))
+        .unwrap_or(s)
+        .strip_prefix('r')?;
+    let num_hashes = after_prefix.len() - after_prefix.trim_start_matches('#').len();
+    let content = after_prefix[num_hashes..].strip_prefix('"')?;
+
+    let terminator = format!("\"{}", "#".repeat(num_hashes));
+    let content_len = content
+        .find(&terminator)
+        .map_or(content.len(), |end| end + terminator.len());
+    Some(s.len() - content.len() + content_len)
+}
+
+/// Number of bytes of the char literal or lifetime at the start of `s`, and whether it's a char
+/// literal
+fn char_or_lifetime_len(s: &str) -> (usize, bool) {
+    let after_quote = &s[1..];
+    let mut chars = after_quote.chars();
+    match (chars.next(), chars.next()) {
+        (Some('\\'), _) => {
+            // Escaped char literal. Start searching for the end quote after the escaped character,
+            // which may be a quote itself
+            let escape_len = 1 + after_quote[1..].chars().next().map_or(0, char::len_utf8);
+            let len = after_quote[escape_len..]
+                .find('\'')
+                .map_or(s.len(), |end| 1 + escape_len + end + 1);
+            (len, true)
+        }
+        (Some(c), Some('\'')) => (1 + c.len_utf8() + 1, true),
+        _ => (1 + ident_len(after_quote), false),
+    }
+}
+
+fn ident_len(s: &str) -> usize {
+    s.find(|c: char| !(c.is_alphanumeric() || c == '_'))
+        .unwrap_or(s.len())
+}
+
+fn number_len(s: &str) -> usize {
+    let mut len = ident_len(s);
+    // Decimal point, but not a range (`1..2`) or method call (`1.max(2)`)
+    while s[len..].starts_with('.') && s[len + 1..].starts_with(|c: char| c.is_ascii_digit()) {
+        len += 1 + ident_len(&s[len + 1..]);
+    }
+    len
+}
+
+struct Lexer<'a> {
+    code: &'a str,
+    pos: usize,
+}
+
+impl<'a> Iterator for Lexer<'a> {
+    type Item = (Token<'a>, Range<usize>);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            let rest = &self.code[self.pos..];
+            let start = self.pos;
+            let c = rest.chars().next()?;
+
+            let (token, len) = if c.is_whitespace() {
+                self.pos += c.len_utf8();
+                continue;
+            } else if rest.starts_with("//") {
+                let len = rest.find('\n').unwrap_or(rest.len());
+                if !rest.starts_with("//!") {
+                    self.pos += len;
+                    continue;
+                }
+                (Token::InnerDocComment, len)
+            } else if rest.starts_with("/*") {
+                let len = block_comment_len(rest);
+                if !rest.starts_with("/*!") {
+                    self.pos += len;
+                    continue;
+                }
+                (Token::InnerDocComment, len)
+            } else if let Some(len) = raw_string_len(rest) {
+                (Token::Literal, len)
+            } else if c == '"' {
+                (Token::Literal, quoted_len(rest))
+            } else if rest.starts_with("b\"") || rest.starts_with("c\"") {
+                (Token::Literal, 1 + quoted_len(&rest[1..]))
+            } else if rest.starts_with("b'") {
+                (Token::Literal, 1 + char_or_lifetime_len(&rest[1..]).0)
+            } else if c == '\'' {
+                match char_or_lifetime_len(rest) {
+                    (len, true) => (Token::Literal, len),
+                    (len, false) => (Token::Lifetime, len),
+                }
+            } else if rest.starts_with("r#") && rest[2..].starts_with(|c: char| c.is_alphabetic()) {
+                // Raw identifier
+                let len = 2 + ident_len(&rest[2..]);
+                (Token::Ident(&rest[2..len]), len)
+            } else if c.is_ascii_digit() {
+                (Token::Literal, number_len(rest))
+            } else if c.is_alphabetic() || c == '_' {
+                let len = ident_len(rest);
+                (Token::Ident(&rest[..len]), len)
+            } else {
+                (Token::Punct(c), c.len_utf8())
+            };
+
+            self.pos += len;
+            return Some((token, start..self.pos));
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ChunkKind<'a> {
+    /// Inner attribute like `#![feature(...)]` or inner doc comment. Must go at the top of the crate
+    CrateAttribute,
+    /// Function, struct, impl block, use declaration etc.
+    Item {
+        /// The keyword that determines the kind of item, e.g. `fn` or `struct`. `macro` for macro
+        /// invocations like `thread_local! { ... }`
+        keyword: &'a str,
+        /// Name of the function, struct etc. None for items without name like impl blocks
+        name: Option<&'a str>,
+        is_pub: bool,
+    },
+    /// Statement which needs to go inside a function, like `let x = 5;`
+    Statement,
+    /// Statement without trailing semicolon at the end of the snippet, whose value is the value of
+    /// the snippet
+    TailExpression,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct Chunk<'a> {
+    pub kind: ChunkKind<'a>,
+    /// Source code of this chunk, including preceding comments
+    pub text: &'a str,
+}
+
+/// Where a chunk of tokens ends
+#[derive(Clone, Copy, PartialEq)]
+enum ChunkEnd {
+    /// At the next semicolon, e.g. `use` declarations and `let` statements
+    Semicolon,
+    /// At the next semicolon or closing brace, e.g. functions, structs and `if` expressions
+    SemicolonOrBrace,
+}
+
+/// Skips an outer attribute (`#[...]`) or a delimited group (`(...)`, `[...]` or `{...}`) starting
+/// at `i` and returns the index after it. If there's none, returns `i`
+fn skip_group(tokens: &[(Token<'_>, Range<usize>)], mut i: usize) -> usize {
+    if tokens.get(i).map(|t| t.0) == Some(Token::Punct('#')) {
+        i += 1;
+    }
+    if !matches!(
+        tokens.get(i).map(|t| t.0),
+        Some(Token::Punct('(' | '[' | '{'))
+    ) {
+        return i;
+    }
+
+    let mut depth = 0;
+    while let Some((token, _)) = tokens.get(i) {
+        match token {
+            Token::Punct('(' | '[' | '{') => depth += 1,
+            Token::Punct(')' | ']' | '}') => depth -= 1,
+            _ => {}
+        }
+        i += 1;
+        if depth == 0 {
+            break;
+        }
+    }
+    i
+}
+
+/// Figures out what kind of chunk starts at token index `start` and how it is terminated
+fn classify_chunk<'a>(
+    tokens: &[(Token<'a>, Range<usize>)],
+    start: usize,
+) -> (ChunkKind<'a>, ChunkEnd) {
+    let ident_at = |i: usize| match tokens.get(i) {
+        Some((Token::Ident(ident), _)) => Some(*ident),
+        _ => None,
+    };
+    let punct_at = |i: usize| match tokens.get(i) {
+        Some((Token::Punct(c), _)) => Some(*c),
+        _ => None,
+    };
+
+    // Skip outer attributes and visibility
+    let mut i = start;
+    while punct_at(i) == Some('#') && punct_at(i + 1) == Some('[') {
+        i = skip_group(tokens, i);
+    }
+    let is_pub = ident_at(i) == Some("pub");
+    if is_pub {
+        i = skip_group(tokens, i + 1);
+    }
+
+    // Skip qualifiers like `unsafe` or `extern "C"`, but only if a function, impl block or trait
+    // follows. Otherwise, this may be an `unsafe { ... }` block or an `async move { ... }` block
+    let mut j = i;
+    loop {
+        match ident_at(j) {
+            Some("const" | "async" | "unsafe") => j += 1,
+            Some("extern") if ident_at(j + 1) != Some("crate") => {
+                j += 1;
+                if let Some((Token::Literal, _)) = tokens.get(j) {
+                    j += 1; // ABI string
+                }
+            }
+            _ => break,
+        }
+    }
+    if matches!(ident_at(j), Some("fn" | "impl" | "trait")) {
+        i = j;
+    }
+
+    let item = |keyword: &'a str, name_index: usize, end: ChunkEnd| {
+        let name = match ident_at(name_index) {
+            Some("mut") => ident_at(name_index + 1),
+            name => name,
+        };
+        (
+            ChunkKind::Item {
+                keyword,
+                name,
+                is_pub,
+            },
+            end,
+        )
+    };
+
+    match ident_at(i) {
+        Some(keyword @ ("fn" | "struct" | "enum" | "trait" | "mod")) => {
+            item(keyword, i + 1, ChunkEnd::SemicolonOrBrace)
+        }
+        Some("union") if ident_at(i + 1).is_some() => {
+            item("union", i + 1, ChunkEnd::SemicolonOrBrace)
+        }
+        Some("auto") if ident_at(i + 1) == Some("trait") => {
+            item("trait", i + 2, ChunkEnd::SemicolonOrBrace)
+        }
+        Some("impl") => item("impl", usize::MAX, ChunkEnd::SemicolonOrBrace),
+        Some("extern") if ident_at(i + 1) == Some("crate") => {
+            item("extern", i + 2, ChunkEnd::Semicolon)
+        }
+        Some("extern") => item("extern", usize::MAX, ChunkEnd::SemicolonOrBrace),
+        Some(keyword @ ("const" | "static")) if ident_at(i + 1).is_some() => {
+            item(keyword, i + 1, ChunkEnd::Semicolon)
+        }
+        Some("use") => item("use", usize::MAX, ChunkEnd::Semicolon),
+        Some("type") => item("type", i + 1, ChunkEnd::Semicolon),
+        Some("macro_rules") if punct_at(i + 1) == Some('!') => {
+            item("macro_rules", i + 2, ChunkEnd::SemicolonOrBrace)
+        }
+        // Macro invocations with braces are usually item-like, e.g. `thread_local! { ... }`
+        Some(_) if punct_at(i + 1) == Some('!') && punct_at(i + 2) == Some('{') => {
+            item("macro", usize::MAX, ChunkEnd::SemicolonOrBrace)
+        }
+        Some("if" | "match" | "for" | "while" | "loop" | "unsafe" | "async") => {
+            (ChunkKind::Statement, ChunkEnd::SemicolonOrBrace)
+        }
+        _ if punct_at(i) == Some('{') => (ChunkKind::Statement, ChunkEnd::SemicolonOrBrace),
+        // Labeled loop
+        _ if tokens.get(i).map(|t| t.0) == Some(Token::Lifetime) => {
+            (ChunkKind::Statement, ChunkEnd::SemicolonOrBrace)
+        }
+        _ => (ChunkKind::Statement, ChunkEnd::Semicolon),
+    }
+}
+
+/// Returns the index of the last token of the chunk starting at `start`
+fn find_chunk_end(tokens: &[(Token<'_>, Range<usize>)], start: usize, end: ChunkEnd) -> usize {
+    let mut depth = 0;
+    for (i, (token, _)) in tokens.iter().enumerate().skip(start) {
+        match token {
+            Token::Punct('(' | '[' | '{') => depth += 1,
+            Token::Punct(')' | ']') => depth -= 1,
+            Token::Punct('}') => {
+                depth -= 1;
+                // Block-like expressions may continue after the closing brace, like
+                // `if a {} else {}` or `match a {}.unwrap()`
+                let continues = matches!(
+                    tokens.get(i + 1).map(|t| t.0),
+                    Some(Token::Ident("else") | Token::Punct('.' | '?'))
+                );
+                if depth <= 0 && end == ChunkEnd::SemicolonOrBrace && !continues {
+                    // Include a redundant semicolon, like in `match a {};`
+                    return match tokens.get(i + 1).map(|t| t.0) {
+                        Some(Token::Punct(';')) => i + 1,
+                        _ => i,
+                    };
+                }
+            }
+            Token::Punct(';') if depth <= 0 => return i,
+            _ => {}
+        }
+    }
+    tokens.len() - 1
+}
+
+/// Splits the code into top-level chunks, in the order of their appearance
+pub fn split_snippet(code: &str) -> Vec<Chunk<'_>> {
+    let tokens = Lexer { code, pos: 0 }.collect::<Vec<_>>();
+
+    let mut chunks = Vec::new();
+    let mut text_start = 0;
+    let mut i = 0;
+    while i < tokens.len() {
+        let (kind, last_token) = if tokens[i].0 == Token::InnerDocComment {
+            (ChunkKind::CrateAttribute, i)
+        } else if tokens[i].0 == Token::Punct('#')
+            && tokens.get(i + 1).map(|t| t.0) == Some(Token::Punct('!'))
+            && tokens.get(i + 2).map(|t| t.0) == Some(Token::Punct('['))
+        {
+            (ChunkKind::CrateAttribute, skip_group(&tokens, i + 2) - 1)
+        } else {
+            let (kind, end) = classify_chunk(&tokens, i);
+            let last_token = find_chunk_end(&tokens, i, end);
+            let kind = match (kind, &tokens[last_token].0) {
+                (ChunkKind::Statement, Token::Punct(';')) => ChunkKind::Statement,
+                (ChunkKind::Statement, _) if last_token == tokens.len() - 1 => {
+                    ChunkKind::TailExpression
+                }
+                (kind, _) => kind,
+            };
+            (kind, last_token)
+        };
+
+        let text_end = tokens[last_token].1.end;
+        chunks.push(Chunk {
+            kind,
+            text: &code[text_start..text_end],
+        });
+        text_start = text_end;
+        i = last_token + 1;
+    }
+
+    // Trailing comments belong to the last chunk
+    if let Some(last_chunk) = chunks.last_mut() {
+        last_chunk.text = &code[(text_start - last_chunk.text.len())..];
+    }
+
+    chunks
+}
+
+/// Returns whether the code defines a top-level `fn main`
+pub fn has_main(chunks: &[Chunk<'_>]) -> bool {
+    chunks.iter().any(|chunk| {
+        matches!(
+            chunk.kind,
+            ChunkKind::Item {
+                keyword: "fn",
+                name: Some("main"),
+                ..
+            }
+        )
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn kinds(code: &str) -> Vec<ChunkKind<'_>> {
+        split_snippet(code).iter().map(|chunk| chunk.kind).collect()
+    }
+
+    fn fn_item(name: &str) -> ChunkKind<'_> {
+        ChunkKind::Item {
+            keyword: "fn",
+            name: Some(name),
+            is_pub: false,
+        }
+    }
+
+    #[test]
+    fn main_function() {
+        assert!(has_main(&split_snippet("fn main() {}")));
+        assert!(has_main(&split_snippet(
+            "#[allow(unused)]\npub(crate) async fn main() {}"
+        )));
+        assert!(!has_main(&split_snippet("fn main_loop() {}")));
+        assert!(!has_main(&split_snippet("fn foo() { fn main() {} }")));
+    }
+
+    #[test]
+    fn main_function_in_string_or_comment() {
+        assert!(!has_main(&split_snippet(
+            r#"let s = "fn main() {}"; println!("{}", s);"#
+        )));
+        assert!(!has_main(&split_snippet(r##"let s = r#"fn main() {}"#;"##)));
+        assert!(!has_main(&split_snippet("// fn main() {}\nlet x = 5;")));
+        assert!(!has_main(&split_snippet("/* /* */ fn main() {} */ foo();")));
+    }
+
+    #[test]
+    fn unsafe_block_and_function() {
+        assert_eq!(kinds("unsafe { foo() }"), [ChunkKind::TailExpression]);
+        assert_eq!(
+            kinds("unsafe { foo(); }\nbar();"),
+            [ChunkKind::Statement, ChunkKind::Statement]
+        );
+        assert_eq!(kinds("unsafe fn foo() {}"), [fn_item("foo")]);
+        assert_eq!(kinds(r#"unsafe extern "C" fn foo() {}"#), [fn_item("foo")]);
+        assert_eq!(
+            kinds("unsafe impl Send for Foo {}"),
+            [ChunkKind::Item {
+                keyword: "impl",
+                name: None,
+                is_pub: false,
+            }]
+        );
+    }
+
+    #[test]
+    fn tail_expression() {
+        assert_eq!(
+            kinds("let x = 5;\nx + 1"),
+            [ChunkKind::Statement, ChunkKind::TailExpression]
+        );
+        assert_eq!(kinds("let x = 5;\nx + 1;"), [ChunkKind::Statement; 2]);
+        assert_eq!(kinds("if a { 1 } else { 2 }"), [ChunkKind::TailExpression]);
+        assert_eq!(
+            kinds("match a { _ => 1 }.to_string()"),
+            [ChunkKind::TailExpression]
+        );
+        // A block-like expression in the middle is a statement even without semicolon
+        assert_eq!(
+            kinds("for i in 0..3 {}\nvec![1, 2]"),
+            [ChunkKind::Statement, ChunkKind::TailExpression]
+        );
+    }
+
+    #[test]
+    fn items_and_attributes() {
+        let chunks = split_snippet("#![feature(never_type)]\n//! Docs\nconst _: () = ();\nfoo();");
+        let kinds = chunks.iter().map(|chunk| chunk.kind).collect::<Vec<_>>();
+        assert_eq!(
+            kinds,
+            [
+                ChunkKind::CrateAttribute,
+                ChunkKind::CrateAttribute,
+                ChunkKind::Item {
+                    keyword: "const",
+                    name: Some("_"),
+                    is_pub: false,
+                },
+                ChunkKind::Statement,
+            ]
+        );
+        // Every byte of the code ends up in exactly one chunk
+        let text = chunks.iter().map(|chunk| chunk.text).collect::<String>();
+        assert_eq!(
+            text,
+            "#![feature(never_type)]\n//! Docs\nconst _: () = ();\nfoo();"
+        );
+    }
+}
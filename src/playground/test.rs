@@ -0,0 +1,180 @@
+use super::{api::*, playcrates::apply_dependency_header, util::*};
+use crate::{Context, Error};
+
+/// Results of one or more libtest runs, parsed from stdout
+#[derive(Default)]
+struct TestSummary {
+    passed: usize,
+    ignored: usize,
+    /// Names of the failed tests, in the order libtest reported them
+    failed: Vec<String>,
+    /// Test name and captured output of the failed tests
+    failure_outputs: Vec<(String, String)>,
+}
+
+fn parse_test_output(stdout: &str) -> TestSummary {
+    let mut summary = TestSummary::default();
+    let mut current_failure: Option<(String, String)> = None;
+
+    for line in stdout.lines() {
+        if let Some(name) = line
+            .strip_prefix("---- ")
+            .and_then(|line| line.strip_suffix(" stdout ----"))
+        {
+            summary.failure_outputs.extend(current_failure.take());
+            current_failure = Some((name.to_owned(), String::new()));
+        } else if line == "failures:" || line.starts_with("test result:") {
+            summary.failure_outputs.extend(current_failure.take());
+        } else if let Some((_, output)) = &mut current_failure {
+            output.push_str(line);
+            output.push('\n');
+        } else if let Some((name, status)) = line
+            .strip_prefix("test ")
+            .and_then(|line| line.split_once(" ... "))
+        {
+            if status.starts_with("ok") {
+                summary.passed += 1;
+            } else if status.starts_with("FAILED") {
+                summary.failed.push(name.to_owned());
+            } else if status.starts_with("ignored") {
+                summary.ignored += 1;
+            }
+        }
+    }
+    summary.failure_outputs.extend(current_failure);
+
+    summary
+}
+
+/// Extracts the panic message out of the captured output of a failed test. Falls back to the
+/// entire captured output if there's no panic message
+fn extract_panic_message(test_output: &str) -> String {
+    let mut lines = test_output
+        .lines()
+        .filter(|line| !line.starts_with("note: run with `RUST_BACKTRACE"))
+        .skip_while(|line| !(line.starts_with("thread '") && line.contains(" panicked at ")));
+
+    let panic_line = match lines.next() {
+        Some(line) => line,
+        None => return test_output.trim().to_owned(),
+    };
+    let rest = lines.collect::<Vec<_>>().join("\n");
+
+    let message = match panic_line.split_once(" panicked at '") {
+        // Old format: thread 'name' panicked at 'message', src/lib.rs:1:1
+        Some((_, message_start)) => {
+            let message = format!("{}\n{}", message_start, rest);
+            match message.rfind("', ") {
+                Some(message_end) => message[..message_end].to_owned(),
+                None => message,
+            }
+        }
+        // New format: thread 'name' panicked at src/lib.rs:1:1:\nmessage
+        None => rest,
+    };
+    message.trim().to_owned()
+}
+
+/// Formats the test summary into a compact overview of passed and failed tests. Returns None if no
+/// tests ran at all
+fn format_test_summary(summary: &TestSummary) -> Option<String> {
+    if summary.passed + summary.failed.len() + summary.ignored == 0 {
+        return None;
+    }
+
+    let mut output = format!(
+        "✅ {} passed, ❌ {} failed",
+        summary.passed,
+        summary.failed.len()
+    );
+    if summary.ignored > 0 {
+        output += &format!(", {} ignored", summary.ignored);
+    }
+    output += "\n";
+
+    for failed_test in &summary.failed {
+        output += &format!("\n❌ {}\n", failed_test);
+
+        let test_output = summary
+            .failure_outputs
+            .iter()
+            .find(|(name, _)| name == failed_test)
+            .map(|(_, output)| extract_panic_message(output));
+        if let Some(message) = test_output.filter(|message| !message.is_empty()) {
+            output += &message;
+            output += "\n";
+        }
+    }
+
+    Some(output)
+}
+
+/// Run `#[test]` functions and summarize the results
+#[poise::command(
+    prefix_command,
+    track_edits,
+    help_text_fn = "test_help",
+    category = "Playground"
+)]
+pub async fn test(
+    ctx: Context<'_>,
+    flags: poise::KeyValueArgs,
+    code: Option<poise::CodeBlock>,
+) -> Result<(), Error> {
+    let code = resolve_code(ctx, code).await?;
+    send_stub_message(ctx).await?;
+
+    let (flags, mut flag_parse_errors) = parse_flags(flags);
+    let code = apply_dependency_header(ctx, code, &mut flag_parse_errors).await?;
+    let crate_type = crate_type(&flags, &code);
+
+    let request = PlaygroundRequest {
+        code: &code,
+        channel: flags.channel,
+        crate_type,
+        edition: flags.edition,
+        mode: flags.mode,
+        tests: true,
+    };
+    let mut result = run_maybe_cached(
+        ctx,
+        &flags,
+        "execute",
+        &request,
+        queued(ctx, ctx.data().playground.execute(&request)),
+    )
+    .await?;
+
+    // Remove the trailing error message about failed tests, which is redundant with the summary
+    let stderr = format_play_eval_stderr(&result.stderr, flags.warn);
+    result.stderr = stderr
+        .lines()
+        .filter(|line| !line.starts_with("error: test failed"))
+        .map(|line| format!("{}\n", line))
+        .collect();
+
+    if let Some(summary) = format_test_summary(&parse_test_output(&result.stdout)) {
+        result.stdout = summary;
+    } else if result.success {
+        result.stdout = "No tests found. Mark test functions with `#[test]`\n".to_owned();
+    }
+
+    send_reply(ctx, result, &code, &flags, &flag_parse_errors).await
+}
+
+pub fn test_help() -> String {
+    generic_help(GenericHelp {
+        command: "test",
+        desc: "Run the `#[test]` functions in the code and summarize which ones passed and failed",
+        mode_and_channel: true,
+        warn: true,
+        run: false,
+        crate_type: true,
+        example_code: "
+#[test]
+fn it_works() {
+    assert_eq!(2 + 2, 5);
+}
+",
+    })
+}
@@ -1,4 +1,4 @@
-use super::api;
+use super::{api, diagnostics, snippet};
 use crate::{Context, Error};
 use poise::serenity_prelude as serenity;
 
@@ -20,21 +20,12 @@ use std::borrow::Cow;
 pub fn parse_flags(mut args: poise::KeyValueArgs) -> (api::CommandFlags, String) {
     let mut errors = String::new();
 
-    let mut flags = api::CommandFlags {
-        channel: api::Channel::Nightly,
-        mode: api::Mode::Debug,
-        edition: api::Edition::E2021,
-        warn: false,
-        run: false,
-    };
+    let mut flags = api::CommandFlags::default();
 
     macro_rules! pop_flag {
         ($flag_name:literal, $flag_field:expr) => {
-            if let Some(flag) = args.0.remove($flag_name) {
-                match flag.parse() {
-                    Ok(x) => $flag_field = x,
-                    Err(e) => errors += &format!("{}\n", e),
-                }
+            if let Some(x) = pop_flag(&mut args, $flag_name, &mut errors) {
+                $flag_field = x;
             }
         };
     }
@@ -44,6 +35,12 @@ pub fn parse_flags(mut args: poise::KeyValueArgs) -> (api::CommandFlags, String)
     pop_flag!("edition", flags.edition);
     pop_flag!("warn", flags.warn);
     pop_flag!("run", flags.run);
+    if let Some(crate_type) = pop_flag(&mut args, "crate", &mut errors) {
+        flags.crate_type = Some(crate_type);
+    }
+    pop_flag!("overflow", flags.overflow);
+    pop_flag!("version", flags.version);
+    pop_flag!("cache", flags.cache);
 
     for (remaining_flag, _) in args.0 {
         errors += &format!("unknown flag `{}`\n", remaining_flag);
@@ -52,12 +49,165 @@ pub fn parse_flags(mut args: poise::KeyValueArgs) -> (api::CommandFlags, String)
     (flags, errors)
 }
 
+/// Attachments larger than this aren't considered as code, to not overwhelm the playground
+const MAX_CODE_ATTACHMENT_SIZE: u64 = 64 * 1024;
+
+/// Returns the contents of the first Rust code block in the text, without the language
+/// annotation. Code blocks annotated with a different language are skipped. Inline code isn't
+/// accepted, because in other people's messages it's usually part of the prose
+pub fn extract_code_block(text: &str) -> Option<String> {
+    // Every odd segment is the inside of a code block, unless it's the unterminated last one
+    let segments = text.split("```").collect::<Vec<_>>();
+    segments
+        .get(1..segments.len().saturating_sub(1))?
+        .iter()
+        .step_by(2)
+        .find_map(|block| {
+            let (language, code) = match block.split_once('\n') {
+                Some((language, code))
+                    if language
+                        .chars()
+                        .all(|c| c.is_ascii_alphanumeric() || "+-_".contains(c)) =>
+                {
+                    (language, code)
+                }
+                _ => ("", *block),
+            };
+            match language {
+                "" | "rs" | "rust" => Some(code.to_owned()),
+                _ => None,
+            }
+        })
+}
+
+/// Downloads the first `.rs` or `.txt` attachment, if any
+async fn code_from_attachments(
+    attachments: &[serenity::Attachment],
+) -> Result<Option<String>, Error> {
+    let attachment = attachments.iter().find(|attachment| {
+        attachment.filename.ends_with(".rs") || attachment.filename.ends_with(".txt")
+    });
+    let attachment = match attachment {
+        Some(attachment) => attachment,
+        None => return Ok(None),
+    };
+
+    if u64::from(attachment.size) > MAX_CODE_ATTACHMENT_SIZE {
+        return Err(format!(
+            "`{}` is too large. The limit is {} KiB",
+            attachment.filename,
+            MAX_CODE_ATTACHMENT_SIZE / 1024
+        )
+        .into());
+    }
+    let bytes = attachment.download().await?;
+    Ok(Some(String::from_utf8(bytes).map_err(|_| {
+        format!("`{}` is not valid UTF-8", attachment.filename)
+    })?))
+}
+
+/// Returns the first Rust code block in the message, or else the contents of a `.rs` or `.txt`
+/// file attached to it
+pub async fn code_from_message(msg: &serenity::Message) -> Result<Option<String>, Error> {
+    if let Some(code) = extract_code_block(&msg.content) {
+        return Ok(Some(code));
+    }
+    code_from_attachments(&msg.attachments).await
+}
+
+/// Returns the code the command should operate on. That's the code block passed to the command
+/// if any, otherwise a `.rs` or `.txt` file attached to the invoking message, otherwise the code
+/// in the message that the invoking message replies to
+pub async fn resolve_code(
+    ctx: Context<'_>,
+    code: Option<poise::CodeBlock>,
+) -> Result<String, Error> {
+    if let Some(code) = code {
+        return Ok(code.code);
+    }
+
+    if let Context::Prefix(ctx) = ctx {
+        if let Some(code) = code_from_attachments(&ctx.msg.attachments).await? {
+            return Ok(code);
+        }
+        if let Some(referenced_message) = &ctx.msg.referenced_message {
+            if let Some(code) = code_from_message(referenced_message).await? {
+                return Ok(code);
+            }
+        }
+    }
+
+    Err(
+        "Missing code. Put the code into a code block, attach it as a `.rs` file, or reply to a \
+        message that contains code"
+            .into(),
+    )
+}
+
+/// Removes a single flag from the arguments and parses it. Used by commands that accept flags
+/// beyond the ones handled by [`parse_flags`]; call it before passing the arguments on to
+/// [`parse_flags`], which complains about unknown flags.
+///
+/// Parse errors are appended to `errors`, with a trailing newline
+pub fn pop_flag<T: std::str::FromStr>(
+    args: &mut poise::KeyValueArgs,
+    flag_name: &str,
+    errors: &mut String,
+) -> Option<T> {
+    let value = args.0.remove(flag_name)?;
+    match value.parse() {
+        Ok(x) => Some(x),
+        Err(_) => {
+            *errors += &format!("invalid `{}` value `{}`\n", flag_name, value);
+            None
+        }
+    }
+}
+
+/// Removes a flag from the arguments and parses it as a comma-separated list of values. Returns an
+/// empty list if the flag is missing.
+///
+/// Parse errors are appended to `errors`, with a trailing newline
+pub fn pop_flag_list<T: std::str::FromStr>(
+    args: &mut poise::KeyValueArgs,
+    flag_name: &str,
+    errors: &mut String,
+) -> Vec<T> {
+    match args.0.remove(flag_name) {
+        Some(values) => parse_flag_list(flag_name, &values, errors),
+        None => Vec::new(),
+    }
+}
+
+/// Parses a comma-separated list of flag values, like `channel=stable,nightly`.
+///
+/// Parse errors are appended to `errors`, with a trailing newline
+pub fn parse_flag_list<T: std::str::FromStr>(
+    flag_name: &str,
+    values: &str,
+    errors: &mut String,
+) -> Vec<T> {
+    values
+        .split(',')
+        .map(str::trim)
+        .filter(|value| !value.is_empty())
+        .filter_map(|value| match value.parse() {
+            Ok(x) => Some(x),
+            Err(_) => {
+                *errors += &format!("invalid `{}` value `{}`\n", flag_name, value);
+                None
+            }
+        })
+        .collect()
+}
+
 pub struct GenericHelp<'a> {
     pub command: &'a str,
     pub desc: &'a str,
     pub mode_and_channel: bool,
     pub warn: bool,
     pub run: bool,
+    pub crate_type: bool,
     pub example_code: &'a str,
 }
 
@@ -79,6 +229,9 @@ pub fn generic_help(spec: GenericHelp<'_>) -> String {
     if spec.run {
         reply += " run={}";
     }
+    if spec.crate_type {
+        reply += " crate={}";
+    }
     reply += " ``\u{200B}`";
     reply += spec.example_code;
     reply += "``\u{200B}`\n```\n";
@@ -95,56 +248,20 @@ pub fn generic_help(spec: GenericHelp<'_>) -> String {
     if spec.run {
         reply += "- run: true, false (default: false)\n";
     }
-
-    reply
-}
-
-/// Strip the input according to a list of start tokens and end tokens. Everything after the start
-/// token up to the end token is stripped. Remaining trailing or loading empty lines are removed as
-/// well.
-///
-/// If multiple potential tokens could be used as a stripping point, this function will make the
-/// stripped output as compact as possible and choose from the matching tokens accordingly.
-// Note to self: don't use "Finished dev" as a parameter to this, because that will break in release
-// compilation mode
-pub fn extract_relevant_lines<'a>(
-    mut stderr: &'a str,
-    strip_start_tokens: &[&str],
-    strip_end_tokens: &[&str],
-) -> &'a str {
-    // Find best matching start token
-    if let Some(start_token_pos) = strip_start_tokens
-        .iter()
-        .filter_map(|t| stderr.rfind(t))
-        .max()
-    {
-        // Keep only lines after that
-        stderr = match stderr[start_token_pos..].find('\n') {
-            Some(line_end) => &stderr[(line_end + start_token_pos + 1)..],
-            None => "",
-        };
-    }
-
-    // Find best matching end token
-    if let Some(end_token_pos) = strip_end_tokens
-        .iter()
-        .filter_map(|t| stderr.rfind(t))
-        .min()
-    {
-        // Keep only lines before that
-        stderr = match stderr[..end_token_pos].rfind('\n') {
-            Some(prev_line_end) => &stderr[..=prev_line_end],
-            None => "",
-        };
+    if spec.crate_type {
+        reply += "- crate: bin, lib (default: lib if the code consists only of items like \
+            functions and structs, bin otherwise)\n";
     }
+    reply +=
+        "- overflow: link, attachment, paginate (default: link). How to deliver output that's \
+        too long for a Discord message: as a playground link, as file attachments, or split into \
+        pages\n";
+    reply += "- version: true, false (default: false). Show the exact rustc version below the \
+        output\n";
+    reply += "- cache: true, false (default: false). Reuse the result of an identical earlier \
+        run instead of running the code again\n";
 
-    // Strip trailing or leading empty lines
-    stderr = stderr.trim_start_matches('\n');
-    while stderr.ends_with("\n\n") {
-        stderr = &stderr[..(stderr.len() - 1)];
-    }
-
-    stderr
+    reply
 }
 
 pub enum ResultHandling {
@@ -156,63 +273,279 @@ pub enum ResultHandling {
     Print,
 }
 
+/// Moves all crate attributes (`#![...]`) to the top of the code, so that they keep working when
+/// other code is inserted in front of the user's code. `after_crate_attrs` is inserted after the
+/// crate attributes, and `after_code` after the user's code
 pub fn hoise_crate_attributes(code: &str, after_crate_attrs: &str, after_code: &str) -> String {
-    let mut lines = code.lines().peekable();
+    let chunks = snippet::split_snippet(code);
 
     let mut output = String::new();
+    push_chunks(&mut output, &chunks, |kind| {
+        *kind == snippet::ChunkKind::CrateAttribute
+    });
+    output.push_str(after_crate_attrs);
+    push_chunks(&mut output, &chunks, |kind| {
+        *kind != snippet::ChunkKind::CrateAttribute
+    });
+    output.push_str(after_code);
 
-    // First go through the input lines and extract the crate attributes at the start. Those will
-    // be put right at the beginning of the generated code, else they won't work (crate attributes
-    // need to be at the top of the file)
-    while let Some(line) = lines.peek() {
-        let line = line.trim();
-        if line.starts_with("#![") {
-            output.push_str(line);
-            output.push('\n');
-        } else if line.is_empty() {
-            // do nothing, maybe more crate attributes are coming
-        } else {
-            break;
-        }
-        lines.next(); // Advance the iterator
-    }
+    output
+}
 
-    output.push_str(after_crate_attrs);
+/// Guesses whether the code is meant to be compiled as a library, i.e. whether it consists only of
+/// items like functions and structs, and contains nothing that needs to go into a `fn main`
+fn detect_crate_type(code: &str) -> api::CrateType {
+    let chunks = snippet::split_snippet(code);
+    let only_items = chunks.iter().all(|chunk| match chunk.kind {
+        snippet::ChunkKind::CrateAttribute | snippet::ChunkKind::Item { .. } => true,
+        snippet::ChunkKind::Statement | snippet::ChunkKind::TailExpression => false,
+    });
+    let has_items = chunks
+        .iter()
+        .any(|chunk| matches!(chunk.kind, snippet::ChunkKind::Item { .. }));
 
-    // Write the rest of the lines that don't contain crate attributes
-    for line in lines {
-        output.push_str(line);
-        output.push('\n');
+    if only_items && has_items && !snippet::has_main(&chunks) {
+        api::CrateType::Library
+    } else {
+        api::CrateType::Binary
     }
+}
 
-    output.push_str(after_code);
+/// Returns the crate type given via the `crate` flag, or detects it from the code if the flag wasn't
+/// given
+pub fn crate_type(flags: &api::CommandFlags, code: &str) -> api::CrateType {
+    flags.crate_type.unwrap_or_else(|| detect_crate_type(code))
+}
 
-    output
+/// Like [`maybe_wrap`], but leaves library code as is, because libraries don't need a `fn main`
+pub fn maybe_wrap_for_crate_type(
+    code: &str,
+    crate_type: api::CrateType,
+    result_handling: ResultHandling,
+) -> Cow<'_, str> {
+    match crate_type {
+        api::CrateType::Binary => maybe_wrap(code, result_handling),
+        api::CrateType::Library => Cow::Borrowed(code),
+    }
+}
+
+/// Appends the text of the chunks of the given kind to the output, one after another
+fn push_chunks(
+    output: &mut String,
+    chunks: &[snippet::Chunk<'_>],
+    include: impl Fn(&snippet::ChunkKind<'_>) -> bool,
+) {
+    for chunk in chunks.iter().filter(|chunk| include(&chunk.kind)) {
+        output.push_str(chunk.text.trim_start_matches('\n'));
+        output.push('\n');
+    }
 }
 
 /// Utility used by the commands to wrap the given code in a `fn main` if not already wrapped.
 /// To check, whether a wrap was done, check if the return type is Cow::Borrowed vs Cow::Owned
-/// If a wrap was done, also hoists crate attributes to the top so they keep working
+///
+/// If a wrap was done, items like structs and functions stay at the top level, while statements
+/// go into `fn main`. Crate attributes are hoisted to the top so they keep working
 pub fn maybe_wrap(code: &str, result_handling: ResultHandling) -> Cow<'_, str> {
-    if code.contains("fn main") || code.contains("#![no_main]") {
+    let chunks = snippet::split_snippet(code);
+
+    let no_main = chunks.iter().any(|chunk| {
+        chunk.kind == snippet::ChunkKind::CrateAttribute && chunk.text.contains("no_main")
+    });
+    if snippet::has_main(&chunks) || no_main {
         return Cow::Borrowed(code);
     }
 
+    let mut output = String::new();
+
+    // Crate attributes need to be at the very top, else they won't work
+    push_chunks(&mut output, &chunks, |kind| {
+        *kind == snippet::ChunkKind::CrateAttribute
+    });
+    push_chunks(&mut output, &chunks, |kind| {
+        matches!(kind, snippet::ChunkKind::Item { .. })
+    });
+
     // fn main boilerplate
-    let after_crate_attrs = match result_handling {
+    output.push_str(match result_handling {
         ResultHandling::None => "fn main() {\n",
         ResultHandling::Discard => "fn main() { let _ = {\n",
         ResultHandling::Print => "fn main() { println!(\"{:?}\", {\n",
-    };
+    });
+
+    push_chunks(&mut output, &chunks, |kind| {
+        matches!(
+            kind,
+            snippet::ChunkKind::Statement | snippet::ChunkKind::TailExpression
+        )
+    });
 
     // fn main boilerplate counterpart
-    let after_code = match result_handling {
+    output.push_str(match result_handling {
         ResultHandling::None => "}",
         ResultHandling::Discard => "}; }",
         ResultHandling::Print => "}); }",
+    });
+
+    Cow::Owned(output)
+}
+
+/// Line-based diff in the unified format, with three lines of context around each change. Runs on
+/// a blocking thread, since big inputs take a while. If the changed part is too large, a note is
+/// returned instead of the diff
+pub async fn unified_diff(
+    old_name: &str,
+    old: &str,
+    new_name: &str,
+    new: &str,
+) -> Result<String, Error> {
+    let (old_name, old, new_name, new) = (
+        old_name.to_owned(),
+        old.to_owned(),
+        new_name.to_owned(),
+        new.to_owned(),
+    );
+    let diff =
+        tokio::task::spawn_blocking(move || compute_unified_diff(&old_name, &old, &new_name, &new))
+            .await?;
+    Ok(diff.unwrap_or_else(|| String::from("Too many changed lines to show a diff\n")))
+}
+
+/// Returns None if the changed part of the inputs is too large to diff
+fn compute_unified_diff(old_name: &str, old: &str, new_name: &str, new: &str) -> Option<String> {
+    const CONTEXT: usize = 3;
+    /// Upper bound for the size of the LCS table, to keep memory usage and runtime in check
+    const MAX_LCS_CELLS: usize = 4_000_000;
+
+    let old_lines = old.lines().collect::<Vec<_>>();
+    let new_lines = new.lines().collect::<Vec<_>>();
+
+    // Fixes usually touch few lines, so cutting off the common start and end keeps the quadratic
+    // part below small
+    let common_prefix = old_lines
+        .iter()
+        .zip(&new_lines)
+        .take_while(|(old, new)| old == new)
+        .count();
+    let common_suffix = old_lines[common_prefix..]
+        .iter()
+        .rev()
+        .zip(new_lines[common_prefix..].iter().rev())
+        .take_while(|(old, new)| old == new)
+        .count();
+    let old_middle = &old_lines[common_prefix..(old_lines.len() - common_suffix)];
+    let new_middle = &new_lines[common_prefix..(new_lines.len() - common_suffix)];
+    if (old_middle.len() + 1) * (new_middle.len() + 1) > MAX_LCS_CELLS {
+        return None;
+    }
+
+    // lcs[i][j] is the length of the longest common subsequence of old_middle[i..] and
+    // new_middle[j..]
+    let mut lcs = vec![vec![0_u32; new_middle.len() + 1]; old_middle.len() + 1];
+    for i in (0..old_middle.len()).rev() {
+        for j in (0..new_middle.len()).rev() {
+            lcs[i][j] = if old_middle[i] == new_middle[j] {
+                lcs[i + 1][j + 1] + 1
+            } else {
+                lcs[i + 1][j].max(lcs[i][j + 1])
+            };
+        }
+    }
+
+    // Every line of the diff, with its marker and the indices of the old and new line it's at
+    let mut script = Vec::new();
+    for (k, line) in old_lines[..common_prefix].iter().enumerate() {
+        script.push((' ', *line, k, k));
+    }
+    let (mut i, mut j) = (0, 0);
+    while i < old_middle.len() || j < new_middle.len() {
+        let (old_index, new_index) = (common_prefix + i, common_prefix + j);
+        if i < old_middle.len() && j < new_middle.len() && old_middle[i] == new_middle[j] {
+            script.push((' ', old_middle[i], old_index, new_index));
+            i += 1;
+            j += 1;
+        } else if i < old_middle.len() && (j == new_middle.len() || lcs[i + 1][j] >= lcs[i][j + 1])
+        {
+            script.push(('-', old_middle[i], old_index, new_index));
+            i += 1;
+        } else {
+            script.push(('+', new_middle[j], old_index, new_index));
+            j += 1;
+        }
+    }
+    let suffix_start = old_lines.len() - common_suffix;
+    for (k, line) in old_lines[suffix_start..].iter().enumerate() {
+        let new_index = new_lines.len() - common_suffix + k;
+        script.push((' ', *line, suffix_start + k, new_index));
+    }
+
+    // Like in GNU diff, an empty range starts at the line before it
+    let range = |start: usize, count: usize| match count {
+        0 => format!("{},0", start),
+        1 => format!("{}", start + 1),
+        _ => format!("{},{}", start + 1, count),
     };
 
-    Cow::Owned(hoise_crate_attributes(code, after_crate_attrs, after_code))
+    let changes = (0..script.len())
+        .filter(|&k| script[k].0 != ' ')
+        .collect::<Vec<_>>();
+    let mut diff = format!("--- {}\n+++ {}\n", old_name, new_name);
+    let mut k = 0;
+    while k < changes.len() {
+        // Changes whose context would overlap go into the same hunk
+        let first_change = changes[k];
+        let mut last_change = first_change;
+        k += 1;
+        while k < changes.len() && changes[k] - last_change <= 2 * CONTEXT + 1 {
+            last_change = changes[k];
+            k += 1;
+        }
+
+        let hunk = &script[first_change.saturating_sub(CONTEXT)
+            ..usize::min(last_change + CONTEXT + 1, script.len())];
+        let (_, _, old_start, new_start) = hunk[0];
+        let old_count = hunk.iter().filter(|line| line.0 != '+').count();
+        let new_count = hunk.iter().filter(|line| line.0 != '-').count();
+        diff += &format!(
+            "@@ -{} +{} @@\n",
+            range(old_start, old_count),
+            range(new_start, new_count)
+        );
+        for (marker, line, _, _) in hunk {
+            diff += &format!("{}{}\n", marker, line);
+        }
+    }
+    Some(diff)
+}
+
+/// Whether the playground killed the program for running too long
+pub fn is_timeout(output: &str) -> bool {
+    output.contains("Killed                  timeout --signal=KILL")
+}
+
+/// Sends a request that runs code to the playground. Programs may behave differently on every
+/// run, so an identical earlier result is only reused if the `cache` flag is set. Timeouts are
+/// never cached, since the next attempt may well finish in time
+pub async fn run_maybe_cached(
+    ctx: Context<'_>,
+    flags: &api::CommandFlags,
+    kind: &str,
+    request: &impl serde::Serialize,
+    run: impl std::future::Future<Output = Result<api::PlayResult, Error>>,
+) -> Result<api::PlayResult, Error> {
+    if !flags.cache {
+        return run.await;
+    }
+
+    let result_cache = &ctx.data().result_cache;
+    if let Some(result) = result_cache.get(kind, request).await {
+        return Ok(result);
+    }
+    let result = run.await?;
+    if !is_timeout(&result.stderr) {
+        result_cache.insert(kind, request, &result).await;
+    }
+    Ok(result)
 }
 
 /// Send a Discord reply with the formatted contents of a Playground result
@@ -223,90 +556,289 @@ pub async fn send_reply(
     flags: &api::CommandFlags,
     flag_parse_errors: &str,
 ) -> Result<(), Error> {
-    let result = crate::merge_output_and_errors(&result.stdout, &result.stderr);
+    send_reply_with_stdout_name(
+        ctx,
+        result,
+        code,
+        flags,
+        flag_parse_errors,
+        "",
+        "stdout.txt",
+    )
+    .await
+}
+
+/// Like [`send_reply`], but with a custom file name for stdout in case it's sent as an attachment.
+/// Useful for commands whose output is code. `header` is shown between the flag parse errors and
+/// the output and isn't truncated, so it must leave room for the output
+pub async fn send_reply_with_stdout_name(
+    ctx: Context<'_>,
+    result: api::PlayResult,
+    code: &str,
+    flags: &api::CommandFlags,
+    flag_parse_errors: &str,
+    header: &str,
+    stdout_name: &str,
+) -> Result<(), Error> {
+    let error_code_links = diagnostics::error_code_links(&result.stderr);
+    let lint_links = diagnostics::clippy_lint_links(&result.stderr);
+    let output = crate::merge_output_and_errors(&result.stdout, &result.stderr);
+    let version_footer = if flags.version {
+        super::version_footer(ctx.data(), flags.channel).await
+    } else {
+        None
+    };
 
     // Discord displays empty code blocks weirdly if they're not formatted in a specific style,
     // so we special-case empty code blocks
-    if result.trim().is_empty() {
-        ctx.say(format!("{}``` ```", flag_parse_errors)).await?;
+    if output.trim().is_empty() {
+        let mut text = format!("{}{}``` ```", flag_parse_errors, header);
+        if let Some(version_footer) = &version_footer {
+            text += "\n";
+            text += version_footer;
+        }
+        ctx.say(text).await?;
         return Ok(());
     }
 
-    let timeout = result.contains("Killed                  timeout --signal=KILL");
+    let timeout = is_timeout(&output);
 
     let mut text_end = String::from("```");
     if timeout {
         text_end += "Playground timeout detected";
     }
+    if !error_code_links.is_empty() {
+        text_end += "\n";
+        text_end += &error_code_links;
+    }
+    if !lint_links.is_empty() {
+        text_end += "\n";
+        text_end += &lint_links;
+    }
+    if let Some(version_footer) = &version_footer {
+        text_end += "\n";
+        text_end += version_footer;
+    }
 
-    let text = crate::trim_text(
-        &format!("{}```rust\n{}", flag_parse_errors, result),
-        &text_end,
-        async {
-            format!(
-                "Output too large. Playground link: <{}>",
-                api::url_from_gist(flags, &api::post_gist(ctx, code).await.unwrap_or_default()),
-            )
-        },
-    )
+    // Rendered compiler diagnostics contain ANSI escape codes, which Discord only displays in
+    // ansi code blocks
+    let language = if output.contains('\x1b') {
+        "ansi"
+    } else if stdout_name.ends_with(".diff") {
+        "diff"
+    } else {
+        "rust"
+    };
+
+    let text_start = format!("{}{}```{}\n", flag_parse_errors, header, language);
+
+    if flags.overflow == crate::OverflowStrategy::Paginate {
+        let pages = crate::paginate::split_into_pages(&text_start, &output, &text_end);
+        if pages.len() > 1 {
+            // The page buttons take the place of the retry button here
+            return crate::paginate::paginate(ctx, pages.len(), |i| {
+                poise::CreateReply::new().content(pages[i].clone())
+            })
+            .await;
+        }
+    }
+
+    let mut attach_output = false;
+    let text = crate::trim_text(&format!("{}{}", text_start, output), &text_end, async {
+        if flags.overflow == crate::OverflowStrategy::Link {
+            let playground = &ctx.data().playground;
+            match playground.gist(code, flags).await {
+                Ok(url) => return format!("Output too large. Playground link: <{}>", url),
+                // Attaching the output is better than a broken link
+                Err(e) => log::warn!("failed to create gist: {}", e),
+            }
+        }
+        attach_output = true;
+        "Output too large, see the attached files".to_owned()
+    })
     .await;
 
-    let custom_button_id = ctx.id().to_string();
+    let mut attachments = Vec::new();
+    if attach_output {
+        if !result.stdout.is_empty() {
+            attachments.push(serenity::CreateAttachment::bytes(
+                result.stdout.into_bytes(),
+                stdout_name,
+            ));
+        }
+        if !result.stderr.is_empty() {
+            attachments.push(serenity::CreateAttachment::bytes(
+                diagnostics::strip_ansi_codes(&result.stderr).into_bytes(),
+                "stderr.txt",
+            ));
+        }
+    }
+
+    let retry_button_id = ctx.id().to_string();
+    let share_button_id = format!("{}-share", ctx.id());
+    let mut buttons = Vec::new();
+    if timeout {
+        buttons.push(serenity::CreateButton::new(
+            "Retry",
+            serenity::ButtonStyle::Primary,
+            &retry_button_id,
+        ));
+    }
+    // Ephemeral replies, e.g. from the context menu commands, can be shared with everyone
+    let ephemeral = ctx.command().ephemeral && matches!(ctx, Context::Application(_));
+    if ephemeral {
+        buttons.push(serenity::CreateButton::new(
+            "Post publicly",
+            serenity::ButtonStyle::Secondary,
+            &share_button_id,
+        ));
+    }
+    if buttons.is_empty() {
+        ctx.send({
+            let mut b = poise::CreateReply::new().content(text);
+            for attachment in attachments {
+                b = b.attachment(attachment);
+            }
+            b
+        })
+        .await?;
+        return Ok(());
+    }
+
     let response = ctx
         .send({
-            let mut b = poise::CreateReply::new().content(text);
-            if timeout {
-                b = b.components(vec![serenity::CreateActionRow::Buttons(vec![
-                    serenity::CreateButton::new(
-                        "Retry",
-                        serenity::ButtonStyle::Primary,
-                        &custom_button_id,
-                    ),
-                ])]);
+            let mut b = poise::CreateReply::new()
+                .content(text.clone())
+                .components(vec![serenity::CreateActionRow::Buttons(buttons)]);
+            for attachment in attachments.clone() {
+                b = b.attachment(attachment);
             }
             b
         })
         .await?;
-    if let Some(retry_pressed) = response
+    let pressed_button_id = retry_button_id.clone();
+    let press = response
         .message()
         .await?
         .component_interaction_collector(&ctx.discord().shard)
         .filter(std::sync::Arc::new(move |x| {
-            x.data.custom_id == custom_button_id
+            x.data.custom_id == pressed_button_id || x.data.custom_id == share_button_id
         }))
         .timeout(std::time::Duration::from_secs(600))
         .collect_single()
-        .await
-    {
-        retry_pressed.defer(ctx.discord()).await?;
-        ctx.rerun().await?;
-    } else {
-        // If timed out, just remove the button
-        response
-            .edit(ctx, poise::CreateReply::new().components(vec![]))
-            .await?;
+        .await;
+    match press {
+        Some(press) if press.data.custom_id == retry_button_id => {
+            press.defer(ctx.discord()).await?;
+            ctx.rerun().await?;
+        }
+        Some(press) => {
+            press
+                .create_response(
+                    ctx.discord(),
+                    serenity::CreateInteractionResponse::Message(
+                        serenity::CreateInteractionResponseMessage::new()
+                            .content(text)
+                            .add_files(attachments),
+                    ),
+                )
+                .await?;
+            response
+                .edit(ctx, poise::CreateReply::new().components(vec![]))
+                .await?;
+        }
+        // If timed out, just remove the buttons
+        None => {
+            response
+                .edit(ctx, poise::CreateReply::new().components(vec![]))
+                .await?;
+        }
     }
 
     Ok(())
 }
 
+/// Removes the standard library prelude injection from the start of macro expansion output
+pub fn strip_prelude_injection(expanded: &mut String) {
+    if let Some(prelude_end) = expanded.find("extern crate std;\n") {
+        expanded.replace_range(..(prelude_end + "extern crate std;\n".len()), "");
+    }
+}
+
+/// Functions for glue code which runs shell commands on the playground, e.g. to set up a cargo
+/// project. `cmd_run` exits the glue program if the command fails
+pub const SHELL_HELPERS: &str = r#"
+pub fn cmd_run(cmd: &str) {
+    let status = std::process::Command::new("/bin/sh")
+        .args(&["-c", cmd])
+        .status()
+        .unwrap();
+    if !status.success() {
+        std::process::exit(-1);
+    }
+}
+
+pub fn cmd_stdout(cmd: &str) -> String {
+    let output = std::process::Command::new("/bin/sh")
+        .args(&["-c", cmd])
+        .output()
+        .unwrap();
+    String::from_utf8(output.stdout).unwrap()
+}
+"#;
+
+/// Extracts the contents of a `// [dependencies]` header comment, i.e. the following comment lines
+/// with the comment markers removed, for example:
+///
+/// ```rust,ignore
+/// // [dependencies]
+/// // syn = { version = "2", features = ["full"] }
+/// // quote = "1"
+/// ```
+pub fn extract_dependencies_header(code: &str) -> Option<String> {
+    fn strip_comment(line: &str) -> Option<&str> {
+        let line = line.trim_start();
+        line.strip_prefix("//!")
+            .or_else(|| line.strip_prefix("//"))
+            .map(str::trim)
+    }
+
+    let mut lines = code.lines();
+    lines.find(|&line| strip_comment(line) == Some("[dependencies]"))?;
+    // The table ends at the next table, or at the end of a ```cargo block
+    let dependencies = lines
+        .map_while(strip_comment)
+        .take_while(|line| !line.starts_with('[') && !line.starts_with("```"))
+        .collect::<Vec<_>>()
+        .join("\n");
+    Some(dependencies)
+}
+
 // This function must not break when provided non-formatted text with messed up formatting: rustfmt
 // may not be installed on the host's computer!
 pub fn strip_fn_main_boilerplate_from_formatted(text: &str) -> String {
-    // Remove the fn main boilerplate
+    // Remove the fn main boilerplate. Items which were hoisted out of fn main come before it
     let prefix = "fn main() {";
     let postfix = "}";
 
-    let text = match (text.find(prefix), text.rfind(postfix)) {
-        (Some(prefix_pos), Some(postfix_pos)) => text
-            .get((prefix_pos + prefix.len())..postfix_pos)
-            .unwrap_or(text),
-        _ => text,
+    let (items, text) = match (text.find(prefix), text.rfind(postfix)) {
+        (Some(prefix_pos), Some(postfix_pos)) => {
+            match text.get((prefix_pos + prefix.len())..postfix_pos) {
+                Some(body) => (text[..prefix_pos].trim(), body),
+                None => ("", text),
+            }
+        }
+        _ => ("", text),
     };
     let text = text.trim();
 
-    // Revert the indent introduced by rustfmt
     let mut output = String::new();
+    if !items.is_empty() {
+        output.push_str(items);
+        output.push_str("\n\n");
+    }
+
+    // Revert the indent introduced by rustfmt
     for line in text.lines() {
         output.push_str(line.strip_prefix("    ").unwrap_or(line));
         output.push('\n');
@@ -314,53 +846,53 @@ pub fn strip_fn_main_boilerplate_from_formatted(text: &str) -> String {
     output
 }
 
-/// Split stderr into compiler output and program stderr output and format the two nicely
+/// Split stderr into compiler output and program stderr output and format the two nicely. Compiler
+/// diagnostics are rendered with ANSI colors
 ///
 /// If the program doesn't compile, the compiler output is returned. If it did compile and run,
 /// compiler output (i.e. warnings) is shown only when show_compiler_warnings is true.
 pub fn format_play_eval_stderr(stderr: &str, show_compiler_warnings: bool) -> String {
-    // Extract core compiler output and remove boilerplate lines from top and bottom
-    let compiler_output = extract_relevant_lines(
-        stderr,
-        &["Compiling playground"],
-        &[
-            "warning emitted",
-            "warnings emitted",
-            "warning: `playground` (bin \"playground\") generated",
-            "warning: `playground` (lib) generated",
-            "error: could not compile",
-            "error: aborting",
-            "Finished ",
-        ],
-    );
+    let output = diagnostics::parse_cargo_output(stderr);
+    let compiler_output = diagnostics::render_diagnostics(&output.diagnostics);
 
-    // If the program actually ran, compose compiler output and program stderr
-    // Using "Finished " here instead of "Running `target" because this method is also used by
-    // e.g. -Zunpretty=XXX family commands which don't actually run anything
-    if stderr.contains("Finished ") {
+    if output.compiled {
         // Program successfully compiled, so compiler output will be just warnings
-        let program_stderr = extract_relevant_lines(stderr, &["Finished ", "Running `target"], &[]);
-
         if show_compiler_warnings {
             // Concatenate compiler output and program stderr with a newline
-            match (compiler_output, program_stderr) {
+            match (compiler_output.as_str(), output.program_stderr.as_str()) {
                 ("", "") => String::new(),
                 (warnings, "") => warnings.to_owned(),
                 ("", stderr) => stderr.to_owned(),
                 (warnings, stderr) => format!("{}\n{}", warnings, stderr),
             }
         } else {
-            program_stderr.to_owned()
+            output.program_stderr
         }
+    } else if compiler_output.is_empty() {
+        // Something went wrong that isn't a rustc diagnostic, e.g. a cargo error. Better show
+        // everything than nothing
+        stderr.to_owned()
     } else {
         // Program didn't get to run, so there must be an error, so we yield the compiler output
         // regardless of whether warn is enabled or not
-        compiler_output.to_owned()
+        compiler_output
     }
 }
 
-pub fn stub_message(ctx: Context<'_>) -> String {
-    let mut stub_message = String::from("_Running code on playground..._\n");
+/// Extracts the compiler diagnostics from stderr of a command which doesn't run the program, like
+/// clippy, and renders them with ANSI colors
+pub fn format_compiler_stderr(stderr: &str) -> String {
+    diagnostics::render_diagnostics(&diagnostics::parse_cargo_output(stderr).diagnostics)
+}
+
+fn stub_message(ctx: Context<'_>, queue_position: Option<usize>) -> String {
+    let mut stub_message = match queue_position {
+        Some(position) => format!(
+            "_Waiting for other code to finish running (position {} in queue)..._\n",
+            position
+        ),
+        None => String::from("_Running code on playground..._\n"),
+    };
 
     if let Context::Prefix(ctx) = ctx {
         if let Some(edit_tracker) = &ctx.framework.options().prefix_options.edit_tracker {
@@ -375,3 +907,31 @@ pub fn stub_message(ctx: Context<'_>) -> String {
     stub_message.truncate(2000);
     stub_message
 }
+
+/// Sends a message saying that the code is running or waiting in the queue (or defers the
+/// response, for application commands)
+pub async fn send_stub_message(ctx: Context<'_>) -> Result<(), Error> {
+    // Interactions must be responded to within three seconds, and the reply to a deferred
+    // interaction replaces the loading indicator, so there's no need for a stub message
+    if let Context::Application(_) = ctx {
+        if ctx.command().ephemeral {
+            ctx.defer_ephemeral().await?;
+        } else {
+            ctx.defer().await?;
+        }
+        return Ok(());
+    }
+
+    let queue = &ctx.data().playground_queue;
+    let queue_position = queue.is_full().then(|| queue.queue_length() + 1);
+    ctx.say(stub_message(ctx, queue_position)).await?;
+    Ok(())
+}
+
+/// Waits until it's our turn to send a request to the playground, and then sends it. The slot in
+/// the queue is only taken for the duration of the request, not while e.g. the buttons below the
+/// reply wait for clicks
+pub async fn queued<T>(ctx: Context<'_>, request: impl std::future::Future<Output = T>) -> T {
+    let _permit = ctx.data().playground_queue.acquire().await;
+    request.await
+}
@@ -0,0 +1,95 @@
+use super::api::*;
+use crate::{Context, Data, Error};
+
+/// Cached tool versions of the playground's release channels
+#[derive(Default, Debug)]
+pub struct PlaygroundVersions {
+    versions: Option<VersionsResponse>,
+    last_update_time: Option<std::time::Instant>,
+}
+
+/// How long the versions are cached before they're fetched again. Nightly changes daily, so this
+/// shouldn't be too long
+const VERSIONS_UPDATE_PERIOD: std::time::Duration = std::time::Duration::from_secs(60 * 60);
+
+async fn fetch_playground_versions(data: &Data) -> Result<VersionsResponse, Error> {
+    {
+        let cache = data.playground_versions.lock().unwrap();
+        if let (Some(versions), Some(last_update_time)) = (&cache.versions, cache.last_update_time)
+        {
+            if last_update_time.elapsed() < VERSIONS_UPDATE_PERIOD {
+                return Ok(versions.clone());
+            }
+        }
+    }
+
+    let versions = data.playground.versions().await?;
+    log::info!("updated playground versions");
+    *data.playground_versions.lock().unwrap() = PlaygroundVersions {
+        versions: Some(versions.clone()),
+        last_update_time: Some(std::time::Instant::now()),
+    };
+    Ok(versions)
+}
+
+/// `1.75.0 (82e1608df 2023-12-21)`
+fn format_tool_version(version: &ToolVersion) -> String {
+    let short_hash = version.hash.get(..9).unwrap_or(&version.hash);
+    format!("{} ({} {})", version.version, short_hash, version.date)
+}
+
+/// Line to put below command output, stating which rustc version produced it. None if the
+/// version couldn't be determined
+pub async fn version_footer(data: &Data, channel: Channel) -> Option<String> {
+    let versions = match fetch_playground_versions(data).await {
+        Ok(versions) => versions,
+        Err(e) => {
+            log::warn!("couldn't fetch playground versions: {}", e);
+            return None;
+        }
+    };
+    let rustc = versions.channel(channel).rustc.as_ref()?;
+    Some(format!("_rustc {}_", format_tool_version(rustc)))
+}
+
+/// Show the versions of rustc and the other tools on the playground
+#[poise::command(
+    prefix_command,
+    slash_command,
+    broadcast_typing,
+    track_edits,
+    category = "Playground"
+)]
+pub async fn versions(
+    ctx: Context<'_>,
+    #[description = "Only show this release channel"] channel: Option<Channel>,
+) -> Result<(), Error> {
+    let versions = fetch_playground_versions(ctx.data()).await?;
+
+    let channels = match channel {
+        Some(channel) => vec![channel],
+        None => vec![Channel::Stable, Channel::Beta, Channel::Nightly],
+    };
+
+    let mut response = String::from("```\n");
+    for channel in channels {
+        let channel_versions = versions.channel(channel);
+        response += channel.name();
+        response += "\n";
+        for (tool, version) in [
+            ("rustc", &channel_versions.rustc),
+            ("cargo", &channel_versions.cargo),
+            ("rustfmt", &channel_versions.rustfmt),
+            ("clippy", &channel_versions.clippy),
+            ("miri", &channel_versions.miri),
+        ] {
+            if let Some(version) = version {
+                response += &format!("  {:<8} {}\n", tool, format_tool_version(version));
+            }
+        }
+    }
+    response += "```";
+
+    ctx.say(response).await?;
+    Ok(())
+}
@@ -0,0 +1,101 @@
+//! Protects the external services we send code to from spam, so that they don't block us
+
+use crate::serenity;
+
+use std::collections::{HashMap, VecDeque};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::{Duration, Instant};
+
+/// Limits how often each user may invoke the commands of a category
+#[derive(Debug)]
+pub struct RateLimiter {
+    max_uses: usize,
+    period: Duration,
+    /// Timestamps of the recent uses, per user and command category. Oldest first
+    uses: std::sync::Mutex<HashMap<(serenity::UserId, &'static str), VecDeque<Instant>>>,
+}
+
+impl RateLimiter {
+    /// Allows `max_uses` invocations per `period`
+    pub fn new(max_uses: usize, period: Duration) -> Self {
+        Self {
+            max_uses,
+            period,
+            uses: Default::default(),
+        }
+    }
+
+    /// Records a use of a command of the given category. If the user exceeded the limit, the use
+    /// isn't recorded and the time until the user may try again is returned
+    pub fn check(&self, user: serenity::UserId, category: &'static str) -> Result<(), Duration> {
+        let now = Instant::now();
+        let mut uses = self.uses.lock().unwrap();
+
+        // Forget about uses that are too long ago to matter
+        uses.retain(|_, timestamps| {
+            while let Some(&oldest) = timestamps.front() {
+                if now.saturating_duration_since(oldest) < self.period {
+                    break;
+                }
+                timestamps.pop_front();
+            }
+            !timestamps.is_empty()
+        });
+
+        let timestamps = uses.entry((user, category)).or_default();
+        if timestamps.len() >= self.max_uses {
+            let oldest = timestamps.front().copied().unwrap_or(now);
+            return Err(self
+                .period
+                .saturating_sub(now.saturating_duration_since(oldest)));
+        }
+        timestamps.push_back(now);
+        Ok(())
+    }
+}
+
+/// Bounds the number of requests that are sent to an external service at the same time. Requests
+/// beyond that wait in a first-come-first-served queue
+#[derive(Debug)]
+pub struct ConcurrencyQueue {
+    semaphore: tokio::sync::Semaphore,
+    /// Number of requests currently waiting for a permit
+    waiting: AtomicUsize,
+}
+
+impl ConcurrencyQueue {
+    pub fn new(max_concurrent_requests: usize) -> Self {
+        Self {
+            semaphore: tokio::sync::Semaphore::new(max_concurrent_requests),
+            waiting: AtomicUsize::new(0),
+        }
+    }
+
+    /// Whether a new request would have to wait in the queue
+    pub fn is_full(&self) -> bool {
+        self.semaphore.available_permits() == 0
+    }
+
+    /// How many requests are currently waiting in the queue
+    pub fn queue_length(&self) -> usize {
+        self.waiting.load(Ordering::SeqCst)
+    }
+
+    /// Waits until a request may be sent. Keep the permit around while the request is running
+    pub async fn acquire(&self) -> tokio::sync::SemaphorePermit<'_> {
+        // Decrements the counter again even if the command is cancelled while waiting
+        struct WaitingGuard<'a>(&'a AtomicUsize);
+        impl Drop for WaitingGuard<'_> {
+            fn drop(&mut self) {
+                self.0.fetch_sub(1, Ordering::SeqCst);
+            }
+        }
+
+        self.waiting.fetch_add(1, Ordering::SeqCst);
+        let _guard = WaitingGuard(&self.waiting);
+        let permit = self.semaphore.acquire().await;
+
+        // We never close the semaphore
+        permit.expect("concurrency queue semaphore was closed")
+    }
+}